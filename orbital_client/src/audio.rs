@@ -1,6 +1,8 @@
 use alto::{Alto, Source};
 use std::{collections::HashMap, sync::Arc};
 
+use orbital_shared::events::{SimEvent, SimEventListener};
+
 use crate::{types::{normalized_f64_to_i16}, config};
 
 pub struct AudioState {
@@ -86,7 +88,11 @@ impl AudioState {
             self.load_sound_wav(name);
         }
 
-        let buffer = self.buffers.get(name).expect("Failed to get buffer");
+        // Missing or unsupported files were already reported by load_sound_wav.
+        let buffer = match self.buffers.get(name) {
+            Some(buffer) => buffer,
+            None => return,
+        };
         for source in self.sources.iter_mut() {
             if source.state() == alto::SourceState::Stopped
                 || source.state() == alto::SourceState::Initial
@@ -101,6 +107,19 @@ impl AudioState {
     }
 }
 
+impl SimEventListener for AudioState {
+    fn on_sim_event(&mut self, _tick_number: i32, event: &SimEvent) {
+        match event {
+            SimEvent::SquadronLaunched { .. } => self.play_sound("sfxD03"),
+            SimEvent::SquadronsClashed { .. } => self.play_sound("sfxD05"),
+            SimEvent::WorldCaptured { .. } | SimEvent::PlayerEliminated { .. } => {
+                self.play_sound("sfxD05")
+            }
+            _ => {}
+        }
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::{thread, time::Duration};
@@ -13,6 +13,37 @@ pub struct Config {
     pub window_size: Option<Vec2i>,
     pub window_pos: Option<Vec2i>,
     pub assets_path: String,
+    #[serde(default)]
+    pub map_seed: Option<u64>,
+    #[serde(default = "default_desync_report_path")]
+    pub desync_report_path: String,
+    #[serde(default = "default_replay_path")]
+    pub replay_path: String,
+    #[serde(skip)]
+    pub replay_file: Option<String>,
+    /// Json file with MatchRules for local matches, the built in rules are used when unset.
+    #[serde(default)]
+    pub rules_file: Option<String>,
+    #[serde(default = "default_server_address")]
+    pub server_address: String,
+    #[serde(default = "default_player_name")]
+    pub player_name: String,
+}
+
+fn default_desync_report_path() -> String {
+    String::from("desync/")
+}
+
+fn default_replay_path() -> String {
+    String::from("replays/")
+}
+
+fn default_server_address() -> String {
+    String::from("127.0.0.1:27007")
+}
+
+fn default_player_name() -> String {
+    String::from("player")
 }
 
 impl Config {
@@ -21,6 +52,13 @@ impl Config {
             window_size: Some(Vec2i::new(1280, 720)),
             window_pos: None,
             assets_path: String::from("assets/"),
+            map_seed: None,
+            desync_report_path: default_desync_report_path(),
+            replay_path: default_replay_path(),
+            replay_file: None,
+            rules_file: None,
+            server_address: default_server_address(),
+            player_name: default_player_name(),
         }
     }
 }
@@ -29,14 +67,30 @@ lazy_static! {
     static ref SINGLETON_INSTANCE: Mutex<Arc<Config>> = Mutex::new(Arc::new(Config::new()));
 }
 
-pub fn config_parse_command_line() {
+/// Applies the command line flags on top of the config file, a flag missing its value is an error.
+pub fn config_parse_command_line() -> Result<(), String> {
     let args: Vec<String> = env::args().collect();
+    let value = |i: usize| args.get(i + 1).ok_or_else(|| format!("Missing value for {}", args[i]));
+
+    for (i, arg) in args.iter().enumerate() {
+        if arg == "-config" {
+            load_config_from_file(value(i)?);
+        }
+    }
 
-    for i in 0..args.len() {
-        if args[i] == "-config" {
-            load_config_from_file(&args[i + 1]);
+    let mut config = get_config().as_ref().clone();
+    for (i, arg) in args.iter().enumerate() {
+        match arg.as_str() {
+            "-seed" => config.map_seed = Some(value(i)?.parse().map_err(|_| "Seed must be a number")?),
+            "-replay" => config.replay_file = Some(value(i)?.clone()),
+            "-rules" => config.rules_file = Some(value(i)?.clone()),
+            "-server" => config.server_address = value(i)?.clone(),
+            "-name" => config.player_name = value(i)?.clone(),
+            _ => {}
         }
     }
+    set_config(config);
+    Ok(())
 }
 
 pub fn set_config(config: Config) {
@@ -0,0 +1,52 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use orbital_shared::ai::AiKind;
+use orbital_shared::controller::PlayerController;
+use orbital_shared::simulation::{PlayerAction, Simulation, Team, MAX_ACTIONS_PER_TICK};
+
+/// Who plays the other slots of a match started from the main menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opponent {
+    Ai(AiKind),
+    Online,
+}
+
+impl Opponent {
+    pub const ALL: [Opponent; 4] = [
+        Opponent::Ai(AiKind::GreedyNearest),
+        Opponent::Ai(AiKind::Defensive),
+        Opponent::Ai(AiKind::Rush),
+        Opponent::Online,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Opponent::Ai(kind) => kind.name(),
+            Opponent::Online => "Online",
+        }
+    }
+
+    pub fn next(&self) -> Opponent {
+        let index = Opponent::ALL.iter().position(|o| o == self).unwrap_or(0);
+        Opponent::ALL[(index + 1) % Opponent::ALL.len()]
+    }
+}
+
+/// The local player, the map's input handling feeds actions in through the sender.
+pub struct HumanController {
+    actions: Receiver<PlayerAction>,
+}
+
+impl HumanController {
+    pub fn new() -> (HumanController, Sender<PlayerAction>) {
+        let (sender, actions) = channel();
+        (HumanController { actions }, sender)
+    }
+}
+
+impl PlayerController for HumanController {
+    fn next_actions(&mut self, _sim: &Simulation, _team: Team, _tick_number: i32) -> Vec<PlayerAction> {
+        // Whatever does not fit in this tick stays in the channel for the next one.
+        self.actions.try_iter().take(MAX_ACTIONS_PER_TICK).collect()
+    }
+}
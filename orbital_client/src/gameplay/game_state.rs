@@ -1,28 +1,82 @@
 use crate::graphics::renderer::{Camera, RenderState};
 use crate::graphics::window::{FrameState, KeyCode};
 
+use std::collections::VecDeque;
+use std::sync::mpsc::Sender;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use orbital_shared::controller::{collect_tick, IdleController, PlayerController};
+use orbital_shared::desync::{ChecksumResult, DesyncDetector, DesyncReport, DEFAULT_DESYNC_HISTORY};
+use orbital_shared::events::{dispatch, MatchStats};
+use orbital_shared::lockstep::{INPUT_DELAY_TICKS, MAX_TICKS_AHEAD};
+use orbital_shared::map_file::MapMetadata;
+use orbital_shared::map_gen::MATCH_MAP_SIZE;
+use orbital_shared::replay::Replay;
+use orbital_shared::rules::MatchRules;
+use orbital_shared::simulation::{MatchResult, PlayerAction, PlayerSetup, Team, Tick};
+use orbital_shared::{DesyncStatePacket, GamePacket, MatchResultPacket, TickActionPacket};
+
+use crate::config::get_config;
+use crate::gameplay::controllers::{HumanController, Opponent};
+use crate::gameplay::lobby::{LobbyAction, LobbyScreen};
 use crate::gameplay::map::*;
+use crate::gameplay::replay::ReplayPlayback;
+use crate::network::{ConnectionState, NetworkEvent};
 use crate::{types::*, State};
 /*
    Server notes:
    - Verify client moves, check team is correct, check move is valid
 */
 
-pub struct Tick {
-    pub tick_number: i32,
-    pub player_a_move: PlayerAction,
-    pub player_b_move: PlayerAction,
-}
-
 pub fn ticks_every_x_seconds(x: i32) -> f32 {
     1.0 / (x as f32)
 }
 
+/// Seed for a match that is not hosted by a server, the config can pin it to reproduce a map.
+pub fn local_map_seed() -> u64 {
+    get_config().map_seed.unwrap_or_else(rand::random)
+}
+
+/// Rules for a match hosted by this client, falls back to the defaults if the rules file is broken.
+pub fn local_match_rules() -> MatchRules {
+    match &get_config().rules_file {
+        Some(path) => MatchRules::load(path).unwrap_or_else(|e| {
+            println!("Failed to load rules {}: {}, using the default rules", path, e);
+            MatchRules::default()
+        }),
+        None => MatchRules::default(),
+    }
+}
+
 pub struct GameState {
     current_map: GameMap,
     tick_rate: f32,
     tick_count: i32,
     tick_timer: f32,
+    desync: DesyncDetector,
+    stats: MatchStats,
+    recording: Option<Replay>,
+    playback: Option<ReplayPlayback>,
+    /// The server's rooms, on screen from getting into its lobby until we are in one of them.
+    lobby: Option<LobbyScreen>,
+    in_match: bool,
+    match_over: bool,
+    opponent: Opponent,
+    /// One per player slot, asked for that slot's action every tick.
+    controllers: Vec<Box<dyn PlayerController>>,
+    human_actions: Option<Sender<PlayerAction>>,
+    /// Online matches only simulate the ticks the server sends, in order.
+    online: bool,
+    server_ticks: VecDeque<Tick>,
+    /// Checksums other peers sent for ticks we have not simulated yet, checked once we did.
+    pending_checksums: Vec<(i32, u64)>,
+}
+
+fn write_desync_report(report: &DesyncReport) {
+    match report.write_to_dir(&get_config().desync_report_path) {
+        Ok(path) => println!("Desync report written to {}", path.display()),
+        Err(e) => println!("Failed to write desync report: {}", e),
+    }
 }
 
 impl GameState {
@@ -32,27 +86,446 @@ impl GameState {
             tick_rate: ticks_every_x_seconds(24),
             tick_count: 0,
             tick_timer: 0.0,
+            desync: DesyncDetector::new(DEFAULT_DESYNC_HISTORY),
+            stats: MatchStats::default(),
+            recording: None,
+            playback: None,
+            lobby: None,
+            in_match: false,
+            match_over: false,
+            opponent: Opponent::ALL[0],
+            controllers: Vec::new(),
+            human_actions: None,
+            online: false,
+            server_ticks: VecDeque::new(),
+            pending_checksums: Vec::new(),
+        }
+    }
+
+    /// Starts a match on a random map, `team` is the slot this client plays and `opponent` who
+    /// plays all the others.
+    pub fn start_match(&mut self, seed: u64, players: PlayerSetup, team: Team, rules: MatchRules, state: &mut State) {
+        self.save_replay();
+
+        self.controllers.clear();
+        self.online = self.opponent == Opponent::Online;
+        self.server_ticks.clear();
+        self.pending_checksums.clear();
+        for slot in players.players() {
+            if slot == team {
+                let (controller, sender) = HumanController::new();
+                self.controllers.push(Box::new(controller));
+                self.human_actions = Some(sender);
+                continue;
+            }
+
+            match self.opponent {
+                Opponent::Ai(kind) => self.controllers.push(kind.create()),
+                // Their actions arrive inside the server's ticks.
+                Opponent::Online => self.controllers.push(Box::new(IdleController)),
+            }
+        }
+
+        let map = GameMap::new_random(
+            MATCH_MAP_SIZE,
+            seed,
+            players.clone(),
+            team,
+            rules.clone(),
+            &mut state.rs,
+        );
+        self.current_map = match map {
+            Ok(map) => map,
+            Err(e) => {
+                println!("Failed to generate the map for seed {}: {}", seed, e);
+                state.ns.disconnect();
+                self.current_map
+                    .show_main_menu_message(self.opponent, &format!("Could not generate the map: {}", e));
+                return;
+            }
+        };
+        self.tick_count = 0;
+        self.tick_timer = 0.0;
+        self.desync = DesyncDetector::new(DEFAULT_DESYNC_HISTORY);
+        self.stats = MatchStats::new(players.player_count());
+        self.playback = None;
+        self.in_match = true;
+        self.match_over = false;
+
+        let metadata = MapMetadata {
+            name: format!("Random {}", seed),
+            author: String::from("map_gen"),
+            player_count: players.player_count() as u32,
+        };
+        self.recording = Some(Replay::new(
+            self.current_map.save(metadata),
+            Some(seed),
+            players,
+            rules,
+        ));
+
+        if self.online {
+            // Nobody can act this early, the server gets empty actions so the first ticks come back right away.
+            for tick_number in 0..INPUT_DELAY_TICKS {
+                self.send_tick_actions(tick_number, Vec::new(), -1, 0, state);
+            }
+        }
+    }
+
+    /// Sends our actions for `tick_number` to the server, along with the checksum after `checksum_tick`.
+    fn send_tick_actions(
+        &mut self,
+        tick_number: i32,
+        actions: Vec<PlayerAction>,
+        checksum_tick: i32,
+        checksum: u64,
+        state: &mut State,
+    ) {
+        let packet = GamePacket::TickAction(TickActionPacket {
+            tick_number,
+            team: self.current_map.team(),
+            actions,
+            checksum_tick,
+            checksum,
+        });
+        if let Err(e) = state.ns.send_packet(packet) {
+            println!("Failed to send tick actions: {}", e);
+        }
+    }
+
+    /// The next tick from the server, None while it has not arrived yet.
+    fn next_server_tick(&mut self) -> Option<Tick> {
+        while let Some(tick) = self.server_ticks.front() {
+            if tick.tick_number >= self.tick_count {
+                break;
+            }
+            self.server_ticks.pop_front();
+        }
+
+        match self.server_ticks.front() {
+            Some(tick) if tick.tick_number == self.tick_count => self.server_ticks.pop_front(),
+            _ => None,
+        }
+    }
+
+    pub fn start_replay(&mut self, replay: Replay, state: &mut State) {
+        self.save_replay();
+
+        self.current_map = GameMap::new_replay(replay.initial_simulation(), &mut state.rs);
+        self.tick_count = 0;
+        self.tick_timer = 0.0;
+        self.playback = Some(ReplayPlayback::new(replay));
+        self.in_match = false;
+        self.match_over = false;
+    }
+
+    /// Writes the match recorded so far to the replay folder, does nothing if there is no recording.
+    pub fn save_replay(&mut self) {
+        let replay = match self.recording.take() {
+            Some(replay) => replay,
+            None => return,
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let path = format!(
+            "{}replay_{}_{}.orep",
+            get_config().replay_path,
+            replay.map_seed.unwrap_or(0),
+            timestamp
+        );
+
+        match replay.save(&path) {
+            Ok(_) => println!("Replay saved to {}", path),
+            Err(e) => println!("Failed to save replay: {}", e),
+        }
+    }
+
+    /// Starts connecting to the configured server, its lobby opens once it let us in.
+    fn join_online_match(&mut self, state: &mut State) {
+        if state.ns.connection_state() == ConnectionState::Connecting {
+            return;
+        }
+
+        let config = get_config();
+        state.ns.connect(&config.server_address, &config.player_name);
+        self.current_map
+            .show_main_menu_message(self.opponent, &format!("Connecting to {}...", config.server_address));
+    }
+
+    /// Drops out of the running match back to the main menu, showing `message`.
+    fn leave_match(&mut self, message: &str, state: &mut State) {
+        self.in_match = false;
+        self.save_replay();
+        self.current_map = GameMap::main_menu(Vec2i::new(250 * 2, 150 * 2), &mut state.rs);
+        self.current_map.show_main_menu_message(self.opponent, message);
+    }
+
+    /// Sends what the player picked in the lobby to the server, its answer comes back as a packet.
+    fn handle_lobby_action(&mut self, action: LobbyAction, state: &mut State) {
+        let sent = match action {
+            LobbyAction::None => Ok(()),
+            LobbyAction::Refresh => state.ns.list_rooms(),
+            LobbyAction::Join(room_id) => state.ns.join_room(room_id),
+            LobbyAction::Create(settings) => state.ns.create_room(settings),
+            LobbyAction::Leave => {
+                state.ns.disconnect();
+                self.lobby = None;
+                self.current_map.show_opponent(self.opponent);
+                return;
+            }
+        };
+
+        // A connection that broke shows up as Lost and closes the lobby.
+        if let Err(e) = sent {
+            println!("Failed to send lobby request: {}", e);
+        }
+    }
+
+    fn handle_network_event(&mut self, event: NetworkEvent, state: &mut State) {
+        match event {
+            NetworkEvent::StateChanged(connection) => {
+                println!("Connection {:?}", connection);
+                let wants_lobby = !self.in_match && self.opponent == Opponent::Online;
+                if connection == ConnectionState::InLobby && wants_lobby {
+                    self.lobby = Some(LobbyScreen::new());
+                }
+                if connection == ConnectionState::Lost && self.lobby.take().is_some() {
+                    self.current_map
+                        .show_main_menu_message(self.opponent, "Lost the connection to the server");
+                }
+                if connection == ConnectionState::Lost && self.online && self.in_match {
+                    self.leave_match("Lost the connection to the server", state);
+                }
+            }
+            NetworkEvent::JoinFailed(e) => {
+                println!("Failed to join {}: {}", get_config().server_address, e);
+                self.current_map
+                    .show_main_menu_message(self.opponent, &format!("Could not join: {}", e));
+            }
+            NetworkEvent::Packet(GamePacket::RoomList(room_list)) => {
+                if let Some(lobby) = &mut self.lobby {
+                    lobby.set_rooms(room_list.rooms);
+                }
+            }
+            NetworkEvent::Packet(GamePacket::JoinRejected(rejected)) => {
+                if let Some(lobby) = &mut self.lobby {
+                    lobby.show_rejection(&format!("Could not join: {}", rejected.reason));
+                }
+            }
+            NetworkEvent::Packet(GamePacket::JoinAccepted(accepted)) => {
+                self.lobby = None;
+                // The player moved on while we were connecting.
+                if self.in_match || self.opponent != Opponent::Online {
+                    state.ns.disconnect();
+                    return;
+                }
+                println!("Joined room {} as {:?}", accepted.room_id, accepted.team);
+                self.start_match(accepted.map_seed, accepted.players, accepted.team, accepted.rules, state)
+            }
+            NetworkEvent::Packet(packet) => self.handle_packet(packet, state),
+        }
+    }
+
+    fn end_match(&mut self, result: MatchResult, state: &mut State) {
+        self.in_match = false;
+        self.match_over = true;
+        self.current_map.show_match_result(&result, &self.stats);
+
+        if state.ns.is_connected() {
+            let packet = GamePacket::MatchResult(MatchResultPacket { result });
+            if let Err(e) = state.ns.send_packet(packet) {
+                println!("Failed to send match result: {}", e);
+            }
+        }
+
+        self.save_replay();
+    }
+
+    /// Compares a peer's checksum with ours, a desync gets reported and our state sent to the peers.
+    fn check_remote_checksum(&mut self, checksum_tick: i32, checksum: u64, state: &mut State) {
+        let first_desync = self.desync.desync_tick().is_none();
+        let (local, remote) = match self.desync.check_remote(checksum_tick, checksum) {
+            ChecksumResult::Mismatch { local, remote } => (local, remote),
+            ChecksumResult::Match => return,
+            // A peer ahead of us, compared once we simulated that tick too.
+            ChecksumResult::Unknown => {
+                let ahead = checksum_tick - self.tick_count;
+                if (0..=MAX_TICKS_AHEAD).contains(&ahead) {
+                    self.pending_checksums.push((checksum_tick, checksum));
+                }
+                return;
+            }
+        };
+
+        println!(
+            "Desync on tick {}, local checksum {:016x}, remote checksum {:016x}",
+            checksum_tick, local, remote
+        );
+        if !first_desync {
+            return;
+        }
+
+        // Written now in case the other side never answers, replaced once its state arrives.
+        if let Some(report) = self.desync.report(checksum_tick, remote, None) {
+            write_desync_report(&report);
+        }
+
+        if let Some(local_state) = self.desync.local_state(checksum_tick) {
+            let packet = GamePacket::DesyncState(DesyncStatePacket {
+                tick_number: checksum_tick,
+                checksum: local,
+                state: local_state.clone(),
+            });
+            if let Err(e) = state.ns.send_packet(packet) {
+                println!("Failed to send desync state: {}", e);
+            }
+        }
+    }
+
+    pub fn handle_packet(&mut self, packet: GamePacket, state: &mut State) {
+        match packet {
+            GamePacket::Tick(tick_packet) if self.online => self.server_ticks.push_back(tick_packet.tick),
+            GamePacket::TickAction(tick_action) => {
+                self.check_remote_checksum(tick_action.checksum_tick, tick_action.checksum, state);
+            }
+            GamePacket::DesyncState(desync_state) => {
+                let report = self.desync.report(
+                    desync_state.tick_number,
+                    desync_state.checksum,
+                    Some(desync_state.state),
+                );
+                if let Some(report) = report {
+                    write_desync_report(&report);
+                }
+            }
+            _ => {}
         }
     }
 
     pub fn update_and_render(&mut self, state : &mut State) {
+        if let Some(playback) = &mut self.playback {
+            playback.update(&mut self.current_map, &state.fs, &state.rs, self.tick_rate);
+            self.current_map.frame_update_and_render(state);
+            playback.render_status(&mut state.rs);
+            return;
+        }
+
+        for event in state.ns.poll_events() {
+            self.handle_network_event(event, state);
+        }
+
+        if let Some(lobby) = &mut self.lobby {
+            let action = lobby.update(&state.fs);
+            self.handle_lobby_action(action, state);
+            // The main menu stays hidden behind the lobby.
+            if let Some(lobby) = &mut self.lobby {
+                lobby.render(state);
+            }
+            return;
+        }
+
         let rs: &mut RenderState = &mut state.rs;
         let fs: &mut FrameState = &mut state.fs;
 
-        self.tick_timer += fs.delta_time;
-        if self.tick_timer > self.tick_rate {
-            self.tick_timer -= self.tick_rate;
+        if self.match_over {
+            if fs.is_key_just_pressed(KeyCode::Enter) {
+                self.current_map = GameMap::main_menu(Vec2i::new(250 * 2, 150 * 2), &mut state.rs);
+                self.current_map.show_opponent(self.opponent);
+                self.match_over = false;
+            }
+
+            self.current_map.frame_update_and_render(state);
+            return;
+        }
+
+        if !self.in_match {
+            if fs.is_key_just_pressed(KeyCode::Tab) {
+                self.opponent = self.opponent.next();
+                self.current_map.show_opponent(self.opponent);
+            }
+
+            if fs.is_key_just_pressed(KeyCode::Enter) {
+                match self.opponent {
+                    Opponent::Online => self.join_online_match(state),
+                    Opponent::Ai(_) => self.start_match(
+                        local_map_seed(),
+                        GameMap::local_players(),
+                        Team::player(0),
+                        local_match_rules(),
+                        state,
+                    ),
+                }
+            }
 
-            let tick = Tick {
-                tick_number: self.tick_count,
-                player_a_move: self.current_map.get_next_action(),
-                player_b_move: PlayerAction::None,
+            self.current_map.frame_update_and_render(state);
+            return;
+        }
+
+        if let Some(human_actions) = &self.human_actions {
+            for action in self.current_map.take_actions() {
+                let _ = human_actions.send(action);
+            }
+        }
+
+        self.tick_timer += state.fs.delta_time;
+        if self.tick_timer > self.tick_rate {
+            let tick = if self.online {
+                self.next_server_tick()
+            } else {
+                Some(collect_tick(self.current_map.simulation(), self.tick_count, &mut self.controllers))
             };
-            self.current_map.tick(&tick);
-            self.tick_count += 1;
-            //println!("tick {}", self.tick_count);
+
+            match tick {
+                Some(tick) => {
+                    self.tick_timer -= self.tick_rate;
+                    self.run_tick(tick, state);
+                }
+                // Waiting for the server, the world stays put until the tick shows up.
+                None => self.tick_timer = self.tick_rate,
+            }
         }
 
+        self.current_map.set_tick_progress(self.tick_timer / self.tick_rate);
         self.current_map.frame_update_and_render(state);
     }
+
+    fn run_tick(&mut self, tick: Tick, state: &mut State) {
+        self.current_map.tick(&tick);
+        let events = self.current_map.simulation().events().to_vec();
+        dispatch(tick.tick_number, &events, &mut [&mut state.ad, &mut self.stats]);
+        if let Some(recording) = &mut self.recording {
+            recording.record_tick(&tick);
+        }
+        let checksum = self.desync.record_tick(&tick, self.current_map.simulation());
+
+        if self.online {
+            // Whatever the player did since the last tick goes out for a tick that is not simulated yet.
+            let team = self.current_map.team();
+            let action_tick = tick.tick_number + INPUT_DELAY_TICKS;
+            let actions = match team.index().and_then(|slot| self.controllers.get_mut(slot)) {
+                Some(controller) => controller.next_actions(self.current_map.simulation(), team, action_tick),
+                None => Vec::new(),
+            };
+            self.send_tick_actions(action_tick, actions, tick.tick_number, checksum, state);
+        }
+
+        self.tick_count += 1;
+
+        let simulated = self.tick_count;
+        let (due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_checksums)
+            .into_iter()
+            .partition(|(checksum_tick, _)| *checksum_tick < simulated);
+        self.pending_checksums = pending;
+        for (checksum_tick, checksum) in due {
+            self.check_remote_checksum(checksum_tick, checksum, state);
+        }
+
+        if let Some(result) = self.current_map.simulation().result().cloned() {
+            self.end_match(result, state);
+        }
+        //println!("tick {}", self.tick_count);
+    }
 }
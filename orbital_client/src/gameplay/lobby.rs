@@ -0,0 +1,325 @@
+use orbital_shared::rules::MatchRules;
+use orbital_shared::simulation::{MAX_PLAYERS, MIN_PLAYERS};
+use orbital_shared::{RoomInfo, RoomSettings};
+
+use crate::config::get_config;
+use crate::graphics::ui::*;
+use crate::graphics::window::{FrameState, KeyCode, DIGIT_KEYS};
+use crate::State;
+
+use super::game_state::local_match_rules;
+
+/*
+   The server's lobby as the player sees it. It lists the rooms to join and
+   has a form to open a new one, everything is done with the keyboard like in
+   the main menu. The screen only decides what the player asked for,
+   GameState sends it to the server and feeds the answers back in.
+*/
+
+/// How often the room list is asked for again while it is on screen.
+const REFRESH_INTERVAL: f32 = 3.0;
+
+/// What the player wants from the server, or to get out of the lobby.
+pub enum LobbyAction {
+    None,
+    Refresh,
+    Join(u64),
+    Create(RoomSettings),
+    Leave,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FormField {
+    PlayerCount,
+    Seed,
+    Rules,
+}
+
+const FORM_FIELDS: [FormField; 3] = [FormField::PlayerCount, FormField::Seed, FormField::Rules];
+
+/// The settings of the room the player is about to open.
+struct RoomForm {
+    field: usize,
+    player_count: u32,
+    /// Typed in digit by digit, empty leaves the map to the server.
+    seed: String,
+    rules: usize,
+}
+
+impl RoomForm {
+    fn new() -> RoomForm {
+        RoomForm {
+            field: 0,
+            player_count: MIN_PLAYERS,
+            seed: String::new(),
+            rules: 0,
+        }
+    }
+
+    fn field(&self) -> FormField {
+        FORM_FIELDS[self.field]
+    }
+}
+
+pub struct LobbyScreen {
+    rooms: Vec<RoomInfo>,
+    selected: usize,
+    form: Option<RoomForm>,
+    /// The rules a new room can play, by name.
+    rule_choices: Vec<(String, MatchRules)>,
+    message: Option<String>,
+    /// Set while the server has not answered a join or create yet.
+    waiting: bool,
+    refresh_timer: f32,
+    ui: UIMaster,
+}
+
+impl LobbyScreen {
+    pub fn new() -> LobbyScreen {
+        let mut rule_choices = vec![(String::from("Default"), MatchRules::default())];
+        if let Some(path) = &get_config().rules_file {
+            rule_choices.push((path.clone(), local_match_rules()));
+        }
+
+        let mut lobby = LobbyScreen {
+            rooms: Vec::new(),
+            selected: 0,
+            form: None,
+            rule_choices,
+            message: None,
+            waiting: false,
+            refresh_timer: 0.0,
+            ui: UIMaster::new(),
+        };
+        lobby.rebuild_ui();
+        lobby
+    }
+
+    pub fn set_rooms(&mut self, rooms: Vec<RoomInfo>) {
+        // Stay on the same room if it is still there.
+        let selected_id = self.rooms.get(self.selected).map(|room| room.room_id);
+        self.selected = rooms
+            .iter()
+            .position(|room| Some(room.room_id) == selected_id)
+            .unwrap_or(0);
+        self.rooms = rooms;
+        self.rebuild_ui();
+    }
+
+    /// The server turned down our last join or create, we are still in the lobby.
+    pub fn show_rejection(&mut self, message: &str) {
+        self.waiting = false;
+        self.show_message(message);
+    }
+
+    pub fn show_message(&mut self, message: &str) {
+        self.message = Some(message.to_string());
+        self.rebuild_ui();
+    }
+
+    pub fn update(&mut self, fs: &FrameState) -> LobbyAction {
+        if self.waiting {
+            return LobbyAction::None;
+        }
+
+        let action = if self.form.is_some() {
+            self.update_form(fs)
+        } else {
+            self.update_room_list(fs)
+        };
+        if matches!(action, LobbyAction::Join(_) | LobbyAction::Create(_)) {
+            self.waiting = true;
+            self.message = Some(String::from("Waiting for the server..."));
+            self.rebuild_ui();
+        }
+        action
+    }
+
+    fn update_room_list(&mut self, fs: &FrameState) -> LobbyAction {
+        if fs.is_key_just_pressed(KeyCode::Escape) {
+            return LobbyAction::Leave;
+        }
+
+        if fs.is_key_just_pressed(KeyCode::N) {
+            self.form = Some(RoomForm::new());
+            self.message = None;
+            self.rebuild_ui();
+            return LobbyAction::None;
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Up) && self.selected > 0 {
+            self.selected -= 1;
+            self.rebuild_ui();
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Down) && self.selected + 1 < self.rooms.len() {
+            self.selected += 1;
+            self.rebuild_ui();
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Enter) {
+            let message = match self.rooms.get(self.selected) {
+                Some(room) if room.is_open() => return LobbyAction::Join(room.room_id),
+                Some(room) => format!("{} can not be joined anymore", room.name),
+                None => String::from("There is no room to join, press N to open one"),
+            };
+            self.show_message(&message);
+        }
+
+        self.refresh_timer += fs.delta_time;
+        if fs.is_key_just_pressed(KeyCode::R) || self.refresh_timer > REFRESH_INTERVAL {
+            self.refresh_timer = 0.0;
+            return LobbyAction::Refresh;
+        }
+        LobbyAction::None
+    }
+
+    fn update_form(&mut self, fs: &FrameState) -> LobbyAction {
+        let form = match &mut self.form {
+            Some(form) => form,
+            None => return LobbyAction::None,
+        };
+
+        if fs.is_key_just_pressed(KeyCode::Escape) {
+            self.form = None;
+            self.message = None;
+            self.rebuild_ui();
+            return LobbyAction::Refresh;
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Enter) {
+            let map_seed = match form.seed.as_str() {
+                "" => None,
+                // Only digits that keep it a valid u64 are taken in.
+                seed => seed.parse().ok(),
+            };
+            let (_, rules) = &self.rule_choices[form.rules];
+            return LobbyAction::Create(RoomSettings {
+                name: format!("{}'s room", get_config().player_name),
+                player_count: form.player_count,
+                map_seed,
+                rules: rules.clone(),
+            });
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Up) && form.field > 0 {
+            form.field -= 1;
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Down) && form.field + 1 < FORM_FIELDS.len() {
+            form.field += 1;
+        }
+
+        let left = fs.is_key_just_pressed(KeyCode::Left);
+        let right = fs.is_key_just_pressed(KeyCode::Right);
+        match form.field() {
+            FormField::PlayerCount => {
+                if left {
+                    form.player_count = (form.player_count - 1).max(MIN_PLAYERS);
+                }
+                if right {
+                    form.player_count = (form.player_count + 1).min(MAX_PLAYERS);
+                }
+            }
+            FormField::Seed => {
+                for (digit, key) in DIGIT_KEYS.iter().enumerate() {
+                    let typed = format!("{}{}", form.seed, digit);
+                    if fs.is_key_just_pressed(*key) && typed.parse::<u64>().is_ok() {
+                        form.seed = typed;
+                    }
+                }
+                if fs.is_key_just_pressed(KeyCode::Backspace) {
+                    form.seed.pop();
+                }
+            }
+            FormField::Rules => {
+                let count = self.rule_choices.len();
+                if left {
+                    form.rules = (form.rules + count - 1) % count;
+                }
+                if right {
+                    form.rules = (form.rules + 1) % count;
+                }
+            }
+        }
+
+        self.rebuild_ui();
+        LobbyAction::None
+    }
+
+    fn rebuild_ui(&mut self) {
+        let mut stack = Box::new(UIStackPaneContainer::new_vertical());
+
+        match &self.form {
+            Some(form) => {
+                let marker = |field: FormField| if form.field() == field { ">" } else { " " };
+                let seed = if form.seed.is_empty() { "Random" } else { form.seed.as_str() };
+
+                stack.add_child(Box::new(UILabel::new("New room")));
+                stack.add_child(Box::new(UILabel::new(&format!(
+                    "{} Players: {}",
+                    marker(FormField::PlayerCount),
+                    form.player_count
+                ))));
+                stack.add_child(Box::new(UILabel::new(&format!(
+                    "{} Map seed: {}",
+                    marker(FormField::Seed),
+                    seed
+                ))));
+                stack.add_child(Box::new(UILabel::new(&format!(
+                    "{} Rules: {}",
+                    marker(FormField::Rules),
+                    self.rule_choices[form.rules].0
+                ))));
+                stack.add_child(Box::new(UILabel::new(
+                    "Up/Down: pick  Left/Right: change  0-9/Backspace: seed  Enter: open  Escape: back",
+                )));
+            }
+            None => {
+                stack.add_child(Box::new(UILabel::new(&format!("Rooms on {}", get_config().server_address))));
+                if self.rooms.is_empty() {
+                    stack.add_child(Box::new(UILabel::new("No rooms yet")));
+                }
+                for (index, room) in self.rooms.iter().enumerate() {
+                    let marker = if index == self.selected { ">" } else { " " };
+                    let status = if room.in_progress {
+                        "playing"
+                    } else if room.is_open() {
+                        "open"
+                    } else {
+                        "full"
+                    };
+                    stack.add_child(Box::new(UILabel::new(&format!(
+                        "{} {}  {}/{}  {}",
+                        marker,
+                        room.name,
+                        room.players.len(),
+                        room.player_count,
+                        status
+                    ))));
+                }
+                stack.add_child(Box::new(UILabel::new(
+                    "Up/Down: pick  Enter: join  N: new room  R: refresh  Escape: leave",
+                )));
+            }
+        }
+
+        if let Some(message) = &self.message {
+            stack.add_child(Box::new(UILabel::new(message)));
+        }
+
+        let mut ui = UIMaster::new();
+        ui.add_child(
+            stack,
+            UIBlockContainerContraints {
+                x_constraint: UIBlockContainerXConstraint::CENTER,
+                y_constraint: UIBlockContainerYConstraint::CENTER,
+            },
+        );
+        self.ui = ui;
+    }
+
+    pub fn render(&mut self, state: &mut State) {
+        self.ui.update_and_render(state);
+    }
+}
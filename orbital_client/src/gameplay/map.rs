@@ -1,18 +1,21 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use cgmath::{Bounded, InnerSpace, MetricSpace, VectorSpace};
-use rand::rngs::ThreadRng;
-use rand::Rng;
 use serde::__private::ser::constrain;
-use serde::{Deserialize, Serialize};
 
+use orbital_shared::map_file::{MapFile, MapFileError, MapMetadata};
+use orbital_shared::events::{MatchStats, SimEvent, SimEventListener};
+use orbital_shared::map_gen::{self, MapGenError};
+use orbital_shared::rules::MatchRules;
+use orbital_shared::simulation::*;
+
+use crate::gameplay::controllers::Opponent;
 use crate::graphics::renderer::{Camera, RenderState, TextHAlignment, TextVAlignment};
 use crate::graphics::ui::*;
 use crate::graphics::window::{FrameState, KeyCode};
 use crate::{types::*, State};
 
-use super::game_state::Tick;
-
 const SUNSET_CORAL: Vec4 = Vec4::new(0.749, 0.290, 0.184, 1.0);
 const WARM_TERRACOTTA: Vec4 = Vec4::new(0.843, 0.463, 0.263, 1.0);
 const PEACHY_SAND: Vec4 = Vec4::new(0.929, 0.831, 0.666, 1.0);
@@ -46,96 +49,64 @@ const CORAL_PINK: Vec4 = Vec4::new(0.965, 0.459, 0.478, 1.0);
 const PASTEL_ROSE: Vec4 = Vec4::new(0.910, 0.718, 0.584, 1.0);
 const EARTHY_ADOBE: Vec4 = Vec4::new(0.761, 0.522, 0.412, 1.0);
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct MapFile {}
-
-#[derive(PartialEq, Eq, Clone, Copy)]
-pub enum Team {
-    A,
-    B,
+pub trait TeamColor {
+    fn color(&self) -> Vec4;
 }
 
-impl Team {
-    pub fn color(&self) -> Vec4 {
+const PLAYER_COLORS: [Vec4; MAX_PLAYERS as usize] = [
+    SKY_AZURE,
+    ELECTRIC_FUCHSIA,
+    FRESH_MEADOW,
+    SUNNY_MARIGOLD,
+    ZESTY_ORANGE,
+    RADIANT_ORCHID,
+    AQUA_SURF,
+    CORAL_PINK,
+];
+
+impl TeamColor for Team {
+    fn color(&self) -> Vec4 {
         match self {
-            Team::A => SKY_AZURE,
-            Team::B => ELECTRIC_FUCHSIA,
+            Team::Neutral => STORMY_SLATE,
+            Team::Player(index) => PLAYER_COLORS[*index as usize % PLAYER_COLORS.len()],
         }
     }
-
-    pub fn opposite(&self) -> Team {
-        match self {
-            Team::A => Team::B,
-            Team::B => Team::A,
-        }
-    }
-}
-
-#[derive(Clone)]
-pub struct PlayerActionAttack {
-    sources: Vec<i32>, // index into world, this wont work if you add worlds !! Sligh hacky of doing it is to use the position instead
-    target: i32, // index into world, this wont work if you add worlds !! Sligh hacky of doing it is to use the position instead
-}
-
-#[derive(Clone)]
-pub enum PlayerAction {
-    None,
-    Attack(PlayerActionAttack),
 }
 
 pub struct ClientState {
     camera: Camera,
     team: Team,
-    next_action: PlayerAction,
+    pending_actions: Vec<PlayerAction>,
+    send_ratio: SendRatio,
 }
 
-pub struct Squadron {
-    world_pos: Vec2,
-    ship_count: i32,
-    team: Team,
-    source_world: i32,
-    dest_world: i32,
-    distance_in_ticks: i32,
-    travel_in_ticks: i32,
-}
+const FLASH_DURATION: f32 = 0.6;
+const SQUADRON_SIZE: f32 = 5.0;
 
-pub enum PlanetSize {
-    Small,
-    Medium,
-    Large,
+/// Ring that grows out of a world when it changes hands.
+struct WorldFlash {
+    world: WorldId,
+    color: Vec4,
+    time_left: f32,
 }
 
-impl PlanetSize {
-    pub fn size(&self) -> i32 {
-        match self {
-            PlanetSize::Small => 8,
-            PlanetSize::Medium => 12,
-            PlanetSize::Large => 19,
-        }
-    }
-
-    pub fn random(rng: &mut ThreadRng) -> PlanetSize {
-        match rng.gen_range(0..3) {
-            0 => PlanetSize::Small,
-            1 => PlanetSize::Medium,
-            2 => PlanetSize::Large,
-            _ => panic!("Invalid random number"),
-        }
-    }
-}
-
-pub struct World {
-    pos: Vec2i,
-    ship_count: i32,
-    size: PlanetSize,
-    team: Team,
+/// Burst where two squadrons fought in space.
+struct ClashFlash {
+    pos: Vec2,
+    time_left: f32,
 }
 
 pub struct GameMap {
-    size: Vec2i,
-    worlds: Vec<World>,
-    selected_worlds: Vec<i32>,
-    squadrons: Vec<Squadron>,
+    sim: Simulation,
+    selected_worlds: Vec<WorldId>,
+    selected_squadrons: Vec<u32>,
+    squadron_positions: HashMap<u32, Vec2>,
+    /// Where every world was before the last tick, orbiting worlds are drawn in between.
+    previous_world_positions: Vec<Vec2i>,
+    /// How far we are towards the next tick, from 0 to 1.
+    tick_progress: f32,
+    flashes: Vec<WorldFlash>,
+    clash_flashes: Vec<ClashFlash>,
     client: ClientState,
     start_mouse_pos: Vec2,
     end_mouse_pos: Vec2,
@@ -146,14 +117,19 @@ pub struct GameMap {
 impl GameMap {
     pub fn new() -> Self {
         Self {
-            size: Vec2i::new(0, 0),
-            worlds: Vec::new(),
+            sim: Simulation::new(Vec2i::new(0, 0), Vec::new(), GameMap::local_players()),
             selected_worlds: Vec::new(),
-            squadrons: Vec::new(),
+            selected_squadrons: Vec::new(),
+            squadron_positions: HashMap::new(),
+            previous_world_positions: Vec::new(),
+            tick_progress: 1.0,
+            flashes: Vec::new(),
+            clash_flashes: Vec::new(),
             client: ClientState {
                 camera: Camera::new(0, 0),
-                team: Team::A,
-                next_action: PlayerAction::None,
+                team: Team::player(0),
+                pending_actions: Vec::new(),
+                send_ratio: SendRatio::All,
             },
             start_mouse_pos: Vec2::new(0.0, 0.0),
             end_mouse_pos: Vec2::new(0.0, 0.0),
@@ -164,28 +140,46 @@ impl GameMap {
 
     pub fn main_menu(size: Vec2i, rs: &mut RenderState) -> Self {
         Self {
-            size: size,
-            worlds: Vec::new(),
+            sim: Simulation::new(size, Vec::new(), GameMap::local_players()),
             selected_worlds: Vec::new(),
-            squadrons: Vec::new(),
+            selected_squadrons: Vec::new(),
+            squadron_positions: HashMap::new(),
+            previous_world_positions: Vec::new(),
+            tick_progress: 1.0,
+            flashes: Vec::new(),
+            clash_flashes: Vec::new(),
             client: ClientState {
                 camera: Camera::new(0, 0),
-                team: Team::A,
-                next_action: PlayerAction::None,
+                team: Team::player(0),
+                pending_actions: Vec::new(),
+                send_ratio: SendRatio::All,
             },
             start_mouse_pos: Vec2::new(0.0, 0.0),
             end_mouse_pos: Vec2::new(0.0, 0.0),
             is_dragging: false,
-            ui: GameMap::ui_main_menu(),
+            ui: GameMap::ui_main_menu(Opponent::ALL[0]),
         }
     }
 
-    pub fn ui_main_menu() -> UIMaster {
+    pub fn ui_main_menu(opponent: Opponent) -> UIMaster {
+        GameMap::ui_main_menu_with_message(opponent, None)
+    }
+
+    /// The main menu with a line about what went wrong, like a server turning us away.
+    pub fn ui_main_menu_with_message(opponent: Opponent, message: Option<&str>) -> UIMaster {
         let mut ui = UIMaster::new();
         let mut stack = Box::new(UIStackPaneContainer::new_vertical());
         stack.add_child(Box::new(UIButton::new("Play")));
         stack.add_child(Box::new(UIButton::new("Options")));
         stack.add_child(Box::new(UIButton::new("Exit")));
+        stack.add_child(Box::new(UILabel::new(&format!(
+            "Opponent: {} (Tab to change)",
+            opponent.name()
+        ))));
+        stack.add_child(Box::new(UILabel::new("Press Enter to play")));
+        if let Some(message) = message {
+            stack.add_child(Box::new(UILabel::new(message)));
+        }
 
         ui.add_child(
             stack,
@@ -198,44 +192,141 @@ impl GameMap {
         ui
     }
 
-    pub fn new_random(size: Vec2i, rs: &mut RenderState) -> Self {
-        let mut rng = rand::thread_rng();
-        let world_count = rng.gen_range(4..18);
+    /// Two players, used for screens that have no real match behind them.
+    pub fn local_players() -> PlayerSetup {
+        PlayerSetup::free_for_all(2).expect("Two players are always valid")
+    }
 
-        let mut worlds = Vec::with_capacity(world_count);
-        let half_size = size / 2;
+    pub fn new_random(
+        size: Vec2i,
+        seed: u64,
+        players: PlayerSetup,
+        team: Team,
+        rules: MatchRules,
+        rs: &mut RenderState,
+    ) -> Result<Self, MapGenError> {
+        let worlds = map_gen::generate_for_match(size, seed, players.player_count() as u32)?;
+
+        let mut ui = GameMap::test_ui_stuffies();
+        ui.add_child(
+            Box::new(UILabel::new(&format!("Seed: {}", seed))),
+            UIBlockContainerContraints {
+                x_constraint: UIBlockContainerXConstraint::LEFT,
+                y_constraint: UIBlockContainerYConstraint::BOTTOM,
+            },
+        );
 
-        for _ in 0..world_count {
-            let ship_count = rng.gen_range(10..31);
-            let team = if rng.gen_bool(0.5) { Team::A } else { Team::B };
-            worlds.push(World {
-                pos: Vec2i::new(
-                    rng.gen_range(-half_size.x..half_size.x),
-                    rng.gen_range(-half_size.y..half_size.y),
-                ),
-                size: PlanetSize::random(&mut rng),
-                ship_count,
+        Ok(Self {
+            sim: Simulation::with_rules(size, worlds, players, rules),
+            selected_worlds: Vec::new(),
+            selected_squadrons: Vec::new(),
+            squadron_positions: HashMap::new(),
+            previous_world_positions: Vec::new(),
+            tick_progress: 1.0,
+            flashes: Vec::new(),
+            clash_flashes: Vec::new(),
+            client: ClientState {
+                camera: Camera::new(0, 0),
                 team,
-            });
-        }
+                pending_actions: Vec::new(),
+                send_ratio: SendRatio::All,
+            },
+            start_mouse_pos: Vec2::new(0.0, 0.0),
+            end_mouse_pos: Vec2::new(0.0, 0.0),
+            is_dragging: false,
+            ui,
+        })
+    }
+
+    pub fn new_replay(sim: Simulation, rs: &mut RenderState) -> Self {
+        let mut ui = UIMaster::new();
+        ui.add_child(
+            Box::new(UILabel::new("Space: pause  Up/Down: speed  Right: step  Left: back  0-9/Home/End or drag the bar: seek")),
+            UIBlockContainerContraints {
+                x_constraint: UIBlockContainerXConstraint::LEFT,
+                y_constraint: UIBlockContainerYConstraint::BOTTOM,
+            },
+        );
 
         Self {
-            size: size,
-            worlds: worlds,
+            sim,
             selected_worlds: Vec::new(),
-            squadrons: Vec::new(),
+            selected_squadrons: Vec::new(),
+            squadron_positions: HashMap::new(),
+            previous_world_positions: Vec::new(),
+            tick_progress: 1.0,
+            flashes: Vec::new(),
+            clash_flashes: Vec::new(),
             client: ClientState {
                 camera: Camera::new(0, 0),
-                team: Team::A,
-                next_action: PlayerAction::None,
+                team: Team::player(0),
+                pending_actions: Vec::new(),
+                send_ratio: SendRatio::All,
             },
             start_mouse_pos: Vec2::new(0.0, 0.0),
             end_mouse_pos: Vec2::new(0.0, 0.0),
             is_dragging: false,
-            ui: GameMap::test_ui_stuffies(),
+            ui,
         }
     }
 
+    pub fn ui_post_match(result: &MatchResult, team: Team, stats: &MatchStats) -> UIMaster {
+        let mut ui = UIMaster::new();
+
+        let headline = if result.winners.is_empty() {
+            String::from("Draw")
+        } else if result.winners.contains(&team) {
+            String::from("Victory")
+        } else {
+            format!("Defeat, {:?} won", result.winners)
+        };
+
+        let reason = match result.reason {
+            MatchEndReason::Elimination => "by elimination",
+            MatchEndReason::TimeLimit => "on score when time ran out",
+            MatchEndReason::Surrender => "by surrender",
+        };
+
+        let mut stack = Box::new(UIStackPaneContainer::new_vertical());
+        stack.add_child(Box::new(UILabel::new(&headline)));
+        stack.add_child(Box::new(UILabel::new(reason)));
+        stack.add_child(Box::new(UILabel::new(&format!(
+            "Match ended on tick {}",
+            result.tick_number
+        ))));
+        if let Some(own) = stats.player(team) {
+            stack.add_child(Box::new(UILabel::new(&format!(
+                "You launched {} ships, captured {} worlds and lost {}",
+                own.ships_launched, own.worlds_captured, own.worlds_lost
+            ))));
+        }
+        stack.add_child(Box::new(UILabel::new("Press Enter to return to the menu")));
+
+        ui.add_child(
+            stack,
+            UIBlockContainerContraints {
+                x_constraint: UIBlockContainerXConstraint::CENTER,
+                y_constraint: UIBlockContainerYConstraint::CENTER,
+            },
+        );
+
+        ui
+    }
+
+    pub fn show_opponent(&mut self, opponent: Opponent) {
+        self.ui = GameMap::ui_main_menu(opponent);
+    }
+
+    pub fn show_main_menu_message(&mut self, opponent: Opponent, message: &str) {
+        self.ui = GameMap::ui_main_menu_with_message(opponent, Some(message));
+    }
+
+    pub fn show_match_result(&mut self, result: &MatchResult, stats: &MatchStats) {
+        self.selected_worlds.clear();
+        self.selected_squadrons.clear();
+        self.ui = GameMap::ui_post_match(result, self.client.team, stats);
+    }
+
     pub fn ui_network_test() -> UIMaster {
         let mut ui = UIMaster::new();
         let mut left_block = Box::new(UIBlockContainer::new_from_percent(0.5, 1.0));
@@ -330,94 +421,125 @@ impl GameMap {
         ui
     }
 
-    pub fn get_next_action(&mut self) -> PlayerAction {
-        let c = self.client.next_action.clone();
-        self.client.next_action = PlayerAction::None;
-        return c;
-    }
-
-    pub fn handle_player_move(&mut self, player_action: &PlayerAction, player_team: Team) {
-        match player_action {
-            PlayerAction::None => {}
-            PlayerAction::Attack(attack) => {
-                for source in &attack.sources {
-                    let ship_count = self.worlds[*source as usize].ship_count;
-                    let source_world = &self.worlds[*source as usize];
-                    let target_world = &self.worlds[attack.target as usize];
-
-                    let dir = ivec_to_vec(target_world.pos - source_world.pos).normalize();
-                    let pos = ivec_to_vec(source_world.pos);
-                    let distance = magnitude_i32(source_world.pos - target_world.pos);
-                    self.squadrons.push(Squadron {
-                        world_pos: pos,
-                        ship_count: ship_count,
-                        team: player_team,
-                        source_world: *source,
-                        dest_world: attack.target,
-                        distance_in_ticks: distance,
-                        travel_in_ticks: 0,
-                    });
-
-                    self.worlds[*source as usize].ship_count = 0;
-                }
-            }
+    pub fn team(&self) -> Team {
+        self.client.team
+    }
+
+    /// Everything the player did since the last call, in order.
+    pub fn take_actions(&mut self) -> Vec<PlayerAction> {
+        std::mem::take(&mut self.client.pending_actions)
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.sim
+    }
+
+    /// Swaps in a different state, used when a replay seeks backwards.
+    pub fn set_simulation(&mut self, sim: Simulation) {
+        self.sim = sim;
+        self.selected_worlds.clear();
+        self.selected_squadrons.clear();
+        self.squadron_positions.clear();
+        self.previous_world_positions.clear();
+        self.flashes.clear();
+        self.clash_flashes.clear();
+    }
+
+    pub fn set_tick_progress(&mut self, progress: f32) {
+        self.tick_progress = progress.clamp(0.0, 1.0);
+    }
+
+    /// Where a world is drawn, between its position before the last tick and its current one.
+    fn world_draw_pos(&self, index: usize) -> Vec2 {
+        let current = ivec_to_vec(self.sim.worlds()[index].pos);
+        match self.previous_world_positions.get(index) {
+            Some(previous) => ivec_to_vec(*previous).lerp(current, self.tick_progress),
+            None => current,
         }
     }
 
     pub fn tick(&mut self, tick: &Tick) {
-        self.handle_player_move(&tick.player_a_move, Team::A);
-        self.handle_player_move(&tick.player_b_move, Team::B);
+        self.previous_world_positions = self.sim.worlds().iter().map(|world| world.pos).collect();
+        self.sim.tick(tick);
 
-        if tick.tick_number % 30 == 0 {
-            for world in &mut self.worlds {
-                world.ship_count += 1;
-            }
+        let events = self.sim.events().to_vec();
+        for event in &events {
+            self.on_sim_event(tick.tick_number, event);
         }
 
-        for squadron in &mut self.squadrons {
-            squadron.travel_in_ticks += 1;
-            if squadron.travel_in_ticks == squadron.distance_in_ticks {
-                let dest_world = &mut self.worlds[squadron.dest_world as usize];
+        let squadrons = self.sim.squadrons();
+        self.squadron_positions
+            .retain(|id, _| squadrons.iter().any(|squadron| squadron.id == *id));
+        self.selected_squadrons
+            .retain(|id| squadrons.iter().any(|squadron| squadron.id == *id));
+    }
 
-                if dest_world.team == squadron.team {
-                    dest_world.ship_count += squadron.ship_count;
-                } else {
-                    dest_world.ship_count -= squadron.ship_count;
-                }
+    /// Squadrons are drawn smoothed towards their simulated position.
+    fn squadron_draw_pos(&self, squadron: &Squadron) -> Vec2 {
+        self.squadron_positions
+            .get(&squadron.id)
+            .copied()
+            .unwrap_or_else(|| squadron.pos.to_vec2())
+    }
 
-                if dest_world.ship_count < 0 {
-                    dest_world.team = squadron.team;
-                    dest_world.ship_count = -dest_world.ship_count;
-                }
-            }
+    pub fn get_squadron_under_point(&self, point: Vec2) -> Option<u32> {
+        self.sim
+            .squadrons()
+            .iter()
+            .find(|squadron| is_point_on_circle(self.squadron_draw_pos(squadron), SQUADRON_SIZE, point))
+            .map(|squadron| squadron.id)
+    }
+
+    pub fn get_squadrons_under_box(&self, min: Vec2, max: Vec2) -> Vec<u32> {
+        self.sim
+            .squadrons()
+            .iter()
+            .filter(|squadron| {
+                is_box_overlapping_circle(min, max, self.squadron_draw_pos(squadron), SQUADRON_SIZE)
+            })
+            .map(|squadron| squadron.id)
+            .collect()
+    }
+
+    fn is_own_squadron(&self, id: u32) -> bool {
+        matches!(self.sim.squadron(id), Ok(squadron) if squadron.team == self.client.team)
+    }
+
+    /// Sends the selected squadrons to `target` instead, they stay under their new orders.
+    fn redirect_selected_squadrons(&mut self, target: WorldId) {
+        if self.selected_squadrons.is_empty() {
+            return;
         }
 
-        self.squadrons
-            .retain(|squadron| squadron.travel_in_ticks < squadron.distance_in_ticks)
+        let redirect = PlayerActionRedirect {
+            squadrons: std::mem::take(&mut self.selected_squadrons),
+            target,
+        };
+        self.client.pending_actions.push(PlayerAction::Redirect(redirect));
     }
 
-    pub fn get_world_under_point(&self, point: Vec2) -> Option<i32> {
-        for (i, world) in self.worlds.iter().enumerate() {
-            let world_pos = ivec_to_vec(world.pos);
+    pub fn get_world_under_point(&self, point: Vec2) -> Option<WorldId> {
+        for (index, world) in self.sim.worlds().iter().enumerate() {
+            let world_pos = self.world_draw_pos(index);
             let world_size = world.size.size() as f32;
 
             if is_point_on_circle(world_pos, world_size, point) {
-                return Some(i as i32);
+                return Some(world.id);
             }
         }
 
         None
     }
 
-    pub fn get_worlds_under_box(&self, min: Vec2, max: Vec2) -> Vec<i32> {
+    pub fn get_worlds_under_box(&self, min: Vec2, max: Vec2) -> Vec<WorldId> {
         let mut result = Vec::new();
 
-        for (i, world) in self.worlds.iter().enumerate() {
-            let world_pos = ivec_to_vec(world.pos);
+        for (index, world) in self.sim.worlds().iter().enumerate() {
+            let world_pos = self.world_draw_pos(index);
             let world_size = world.size.size() as f32;
 
             if is_box_overlapping_circle(min, max, world_pos, world_size) {
-                result.push(i as i32);
+                result.push(world.id);
             }
         }
 
@@ -433,6 +555,23 @@ impl GameMap {
         let mouse_pos_world =
             rs.screen_pos_to_world_pos(&self.client.camera, fs.mouse_pos);
 
+        if fs.is_key_just_pressed(KeyCode::F10) {
+            self.client.pending_actions.push(PlayerAction::Surrender);
+        }
+
+        let ratio_keys = [KeyCode::Num1, KeyCode::Num2, KeyCode::Num3, KeyCode::Num4];
+        for (key, ratio) in ratio_keys.iter().zip(SendRatio::ALL_RATIOS.iter()) {
+            if fs.is_key_just_pressed(*key) {
+                self.client.send_ratio = *ratio;
+            }
+        }
+
+        if fs.mouse_scroll > 0.0 {
+            self.client.send_ratio = self.client.send_ratio.higher();
+        } else if fs.mouse_scroll < 0.0 {
+            self.client.send_ratio = self.client.send_ratio.lower();
+        }
+
         if fs.is_mouse_just_pressed(0) {
             self.start_mouse_pos = mouse_pos_world;
             self.is_dragging = false;
@@ -457,48 +596,76 @@ impl GameMap {
 
                 let mut new_selected_worlds = self.get_worlds_under_box(min, max);
 
-                new_selected_worlds.retain(|world_index| {
-                    self.worlds[*world_index as usize].team == self.client.team
+                new_selected_worlds.retain(|world_id| match self.sim.world(*world_id) {
+                    Ok(world) => world.team == self.client.team,
+                    Err(_) => false,
                 });
 
+                let mut new_selected_squadrons = self.get_squadrons_under_box(min, max);
+                new_selected_squadrons.retain(|id| self.is_own_squadron(*id));
+
                 if fs.is_key_pressed(KeyCode::LeftShift) {
                     self.selected_worlds.append(&mut new_selected_worlds);
+                    self.selected_squadrons.append(&mut new_selected_squadrons);
                 } else {
                     self.selected_worlds = new_selected_worlds.clone();
+                    self.selected_squadrons = new_selected_squadrons;
                 }
 
                 self.is_dragging = false;
             } else {
                 match self.get_world_under_point(mouse_pos_world) {
-                    Some(world_index) => {
-                        let world = &mut self.worlds[world_index as usize];
-                        if world.team == self.client.team {
+                    Some(world_id) => {
+                        let world_team = self.sim.world(world_id).map(|world| world.team);
+                        if world_team == Ok(self.client.team) && !self.selected_squadrons.is_empty() {
+                            // Selected squadrons turn around to reinforce one of our worlds.
+                            self.redirect_selected_squadrons(world_id);
+                        } else if world_team == Ok(self.client.team) {
                             if fs.is_key_pressed(KeyCode::LeftShift) {
-                                self.selected_worlds.push(world_index);
+                                self.selected_worlds.push(world_id);
                             } else {
                                 self.selected_worlds.clear();
-                                self.selected_worlds.push(world_index);
+                                self.selected_worlds.push(world_id);
                             }
                         } else {
-                            let attack = PlayerActionAttack {
-                                sources: self.selected_worlds.clone(),
-                                target: world_index,
-                            };
-
-                            self.client.next_action = PlayerAction::Attack(attack);
+                            if !self.selected_worlds.is_empty() {
+                                let attack = PlayerActionAttack {
+                                    sources: self.selected_worlds.clone(),
+                                    target: world_id,
+                                    ratio: self.client.send_ratio,
+                                };
+
+                                self.client.pending_actions.push(PlayerAction::Attack(attack));
+                            }
+                            self.redirect_selected_squadrons(world_id);
                         }
                     }
-                    None => {
-                        self.selected_worlds.clear();
-                    }
+                    None => match self.get_squadron_under_point(mouse_pos_world) {
+                        Some(id) if self.is_own_squadron(id) => {
+                            if !fs.is_key_pressed(KeyCode::LeftShift) {
+                                self.selected_worlds.clear();
+                                self.selected_squadrons.clear();
+                            }
+                            if !self.selected_squadrons.contains(&id) {
+                                self.selected_squadrons.push(id);
+                            }
+                        }
+                        _ => {
+                            self.selected_worlds.clear();
+                            self.selected_squadrons.clear();
+                        }
+                    },
                 }
             }
         }
 
-        for (world_index, world) in self.worlds.iter_mut().enumerate() {
-            let world_pos = Vec2::new(world.pos.x as f32, world.pos.y as f32);
+        let draw_positions: Vec<Vec2> = (0..self.sim.worlds().len())
+            .map(|index| self.world_draw_pos(index))
+            .collect();
+
+        for (world, &world_pos) in self.sim.worlds().iter().zip(&draw_positions) {
 
-            if self.selected_worlds.contains(&(world_index as i32)) {
+            if self.selected_worlds.contains(&world.id) {
                 rs.draw_circle(world.size.size() as f32 + 1.0, world_pos)
                     .with_color(PURE_WHITE);
             }
@@ -511,18 +678,49 @@ impl GameMap {
                 .with_vertical_alignment(TextVAlignment::Center);
         }
 
-        for squadron in &mut self.squadrons {
-            let t = (squadron.travel_in_ticks as f32) / (squadron.distance_in_ticks as f32);
-            let source_world_pos = ivec_to_vec(self.worlds[squadron.source_world as usize].pos);
-            let target_world_pos = ivec_to_vec(self.worlds[squadron.dest_world as usize].pos);
-            let pos = source_world_pos + (target_world_pos - source_world_pos) * t;
-            squadron.world_pos = squadron.world_pos.lerp(pos, 0.40);
-            rs.draw_circle(5.0, squadron.world_pos)
+        for squadron in self.sim.squadrons() {
+            let source_world_pos = squadron.start_pos.to_vec2();
+            let pos = squadron.pos.to_vec2();
+            let world_pos = self
+                .squadron_positions
+                .entry(squadron.id)
+                .or_insert(source_world_pos);
+            *world_pos = world_pos.lerp(pos, 0.40);
+            if self.selected_squadrons.contains(&squadron.id) {
+                rs.draw_circle(SQUADRON_SIZE + 2.0, *world_pos)
+                    .with_color(squadron.team.color());
+            }
+            rs.draw_circle(SQUADRON_SIZE, *world_pos)
                 .with_color(PURE_WHITE);
             //rs.draw_circle(5.0, pos)
             //    .with_color(Vec4::new(1.0, 0.0, 0.0, 0.5));
         }
 
+        for flash in &mut self.flashes {
+            flash.time_left -= fs.delta_time;
+            let index = self.sim.worlds().iter().position(|world| world.id == flash.world);
+            if let Some(index) = index {
+                let world = &self.sim.worlds()[index];
+                let progress = 1.0 - flash.time_left / FLASH_DURATION;
+                let mut color = flash.color;
+                color.w = 1.0 - progress;
+                rs.draw_circle(
+                    world.size.size() as f32 * (1.0 + progress),
+                    draw_positions[index],
+                )
+                .with_color(color);
+            }
+        }
+        self.flashes.retain(|flash| flash.time_left > 0.0);
+
+        for flash in &mut self.clash_flashes {
+            flash.time_left -= fs.delta_time;
+            let progress = 1.0 - flash.time_left / FLASH_DURATION;
+            rs.draw_circle(SQUADRON_SIZE * (1.0 + 2.0 * progress), flash.pos)
+                .with_color(Vec4::new(1.0, 1.0, 1.0, 1.0 - progress));
+        }
+        self.clash_flashes.retain(|flash| flash.time_left > 0.0);
+
         if fs.is_mouse_pressed(0) {
             let min_x = self.start_mouse_pos.x.min(self.end_mouse_pos.x);
             let max_x = self.start_mouse_pos.x.max(self.end_mouse_pos.x);
@@ -538,8 +736,57 @@ impl GameMap {
 
         rs.camera_end();
 
+        if !self.selected_worlds.is_empty() {
+            let text = format!("Send {}%", self.client.send_ratio.percent());
+            rs.draw_text(&text, Vec2::new(10.0, 50.0));
+        }
+
+        if !self.selected_squadrons.is_empty() {
+            let text = format!("Redirect {} squadrons", self.selected_squadrons.len());
+            rs.draw_text(&text, Vec2::new(10.0, 70.0));
+        }
+
         self.ui.update_and_render(state);
     }
 
-    pub fn load(&mut self, map_file: &MapFile) {}
+    pub fn load(&mut self, map_file: &MapFile) -> Result<(), MapFileError> {
+        map_file.validate()?;
+
+        let players = PlayerSetup::free_for_all(map_file.metadata.player_count)
+            .map_err(|_| MapFileError::InvalidPlayerCount(map_file.metadata.player_count))?;
+        self.sim = Simulation::new(map_file.size, map_file.to_worlds(), players);
+        self.selected_worlds.clear();
+        self.selected_squadrons.clear();
+        self.squadron_positions.clear();
+        self.flashes.clear();
+        self.clash_flashes.clear();
+        Ok(())
+    }
+
+    pub fn save(&self, metadata: MapMetadata) -> MapFile {
+        MapFile::from_worlds(metadata, self.sim.size(), self.sim.worlds())
+    }
+}
+
+impl SimEventListener for GameMap {
+    fn on_sim_event(&mut self, _tick_number: i32, event: &SimEvent) {
+        let (world, team) = match event {
+            SimEvent::WorldCaptured { world, team, .. } => (*world, *team),
+            SimEvent::WorldNeutralized { world, .. } => (*world, Team::Neutral),
+            SimEvent::SquadronsClashed { pos, .. } => {
+                self.clash_flashes.push(ClashFlash {
+                    pos: ivec_to_vec(*pos),
+                    time_left: FLASH_DURATION,
+                });
+                return;
+            }
+            _ => return,
+        };
+
+        self.flashes.push(WorldFlash {
+            world,
+            color: team.color(),
+            time_left: FLASH_DURATION,
+        });
+    }
 }
@@ -1,2 +1,5 @@
+pub mod controllers;
 pub mod game_state;
-pub mod map;
\ No newline at end of file
+pub mod lobby;
+pub mod map;
+pub mod replay;
\ No newline at end of file
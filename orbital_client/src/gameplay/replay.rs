@@ -0,0 +1,188 @@
+use orbital_shared::replay::Replay;
+use orbital_shared::simulation::Simulation;
+
+use crate::graphics::renderer::RenderState;
+use crate::graphics::window::{FrameState, KeyCode, DIGIT_KEYS};
+use crate::types::*;
+
+use super::map::GameMap;
+
+pub const REPLAY_SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED_INDEX: usize = 2;
+const SEEK_BACK_TICKS: usize = 24 * 5;
+
+/// A copy of the state is kept every this many ticks, seeking only resimulates from the closest one.
+const CHECKPOINT_INTERVAL_TICKS: usize = 24 * 10;
+
+const SCRUB_BAR_MARGIN: f32 = 10.0;
+const SCRUB_BAR_TOP: f32 = 50.0;
+const SCRUB_BAR_HEIGHT: f32 = 12.0;
+
+pub struct ReplayPlayback {
+    replay: Replay,
+    cursor: usize,
+    paused: bool,
+    speed_index: usize,
+    tick_timer: f32,
+    /// State after `index * CHECKPOINT_INTERVAL_TICKS` ticks, filled in as playback gets there.
+    checkpoints: Vec<Simulation>,
+    scrubbing: bool,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> ReplayPlayback {
+        ReplayPlayback {
+            checkpoints: vec![replay.initial_simulation()],
+            replay,
+            cursor: 0,
+            paused: false,
+            speed_index: NORMAL_SPEED_INDEX,
+            tick_timer: 0.0,
+            scrubbing: false,
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    /// Left and right end of the scrub bar on screen.
+    fn scrub_bar_span(rs: &RenderState) -> (f32, f32) {
+        (SCRUB_BAR_MARGIN, rs.surface_width - SCRUB_BAR_MARGIN)
+    }
+
+    /// Seeks to wherever the mouse is on the scrub bar, for as long as a click that started on
+    /// it is held.
+    fn update_scrubbing(&mut self, map: &mut GameMap, fs: &FrameState, rs: &RenderState) {
+        let (left, right) = ReplayPlayback::scrub_bar_span(rs);
+        let mouse = fs.mouse_pos;
+
+        if fs.is_mouse_just_pressed(0) {
+            self.scrubbing = mouse.y >= SCRUB_BAR_TOP
+                && mouse.y <= SCRUB_BAR_TOP + SCRUB_BAR_HEIGHT
+                && mouse.x >= left
+                && mouse.x <= right;
+        }
+        if !fs.is_mouse_pressed(0) {
+            self.scrubbing = false;
+        }
+
+        if self.scrubbing {
+            let fraction = ((mouse.x - left) / (right - left)).clamp(0.0, 1.0);
+            let tick_count = (fraction * self.replay.tick_count() as f32).round() as usize;
+            if tick_count != self.cursor {
+                self.seek(tick_count, map);
+            }
+        }
+    }
+
+    pub fn update(&mut self, map: &mut GameMap, fs: &FrameState, rs: &RenderState, tick_rate: f32) {
+        if fs.is_key_just_pressed(KeyCode::Space) {
+            self.paused = !self.paused;
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Up) {
+            self.speed_index = (self.speed_index + 1).min(REPLAY_SPEEDS.len() - 1);
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Down) {
+            self.speed_index = self.speed_index.saturating_sub(1);
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Right) && self.paused {
+            self.step(map);
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Left) {
+            self.seek(self.cursor.saturating_sub(SEEK_BACK_TICKS), map);
+        }
+
+        if fs.is_key_just_pressed(KeyCode::Home) {
+            self.seek(0, map);
+        }
+
+        if fs.is_key_just_pressed(KeyCode::End) {
+            self.seek(self.replay.tick_count(), map);
+        }
+
+        // Number keys jump to a tenth of the replay each, 0 to the start and 9 to 90%.
+        for (tenth, key) in DIGIT_KEYS.iter().enumerate() {
+            if fs.is_key_just_pressed(*key) {
+                self.seek(self.replay.tick_count() * tenth / 10, map);
+            }
+        }
+
+        self.update_scrubbing(map, fs, rs);
+
+        if !self.paused {
+            self.tick_timer += fs.delta_time * REPLAY_SPEEDS[self.speed_index];
+            while self.tick_timer > tick_rate {
+                self.tick_timer -= tick_rate;
+                self.step(map);
+            }
+        }
+        map.set_tick_progress(self.tick_timer / tick_rate);
+    }
+
+    pub fn step(&mut self, map: &mut GameMap) {
+        if self.cursor < self.replay.tick_count() {
+            map.tick(&self.replay.ticks[self.cursor]);
+            self.cursor += 1;
+
+            if self.cursor == self.checkpoints.len() * CHECKPOINT_INTERVAL_TICKS {
+                self.checkpoints.push(map.simulation().clone());
+            }
+        }
+    }
+
+    /// Moves to the state after `tick_count` ticks, in either direction. Resimulates from the
+    /// closest checkpoint before it unless the current state is closer.
+    pub fn seek(&mut self, tick_count: usize, map: &mut GameMap) {
+        let tick_count = tick_count.min(self.replay.tick_count());
+
+        let checkpoint = (tick_count / CHECKPOINT_INTERVAL_TICKS).min(self.checkpoints.len() - 1);
+        let checkpoint_tick = checkpoint * CHECKPOINT_INTERVAL_TICKS;
+        if tick_count < self.cursor || checkpoint_tick > self.cursor {
+            map.set_simulation(self.checkpoints[checkpoint].clone());
+            self.cursor = checkpoint_tick;
+        }
+
+        while self.cursor < tick_count {
+            self.step(map);
+        }
+
+        self.tick_timer = 0.0;
+    }
+
+    pub fn render_status(&self, rs: &mut RenderState) {
+        let mut status = format!(
+            "Replay {}/{}  x{}",
+            self.cursor,
+            self.replay.tick_count(),
+            REPLAY_SPEEDS[self.speed_index]
+        );
+
+        if self.paused {
+            status.push_str("  paused");
+        }
+
+        rs.draw_text(&status, Vec2::new(10.0, 30.0));
+
+        let (left, right) = ReplayPlayback::scrub_bar_span(rs);
+        let progress = if self.replay.tick_count() == 0 {
+            0.0
+        } else {
+            self.cursor as f32 / self.replay.tick_count() as f32
+        };
+        rs.draw_rect_min_max(
+            Vec2::new(left, SCRUB_BAR_TOP),
+            Vec2::new(right, SCRUB_BAR_TOP + SCRUB_BAR_HEIGHT),
+        )
+        .with_color(Vec4::new(0.3, 0.3, 0.3, 1.0));
+        rs.draw_rect_min_max(
+            Vec2::new(left, SCRUB_BAR_TOP),
+            Vec2::new(left + (right - left) * progress, SCRUB_BAR_TOP + SCRUB_BAR_HEIGHT),
+        )
+        .with_color(Vec4::new(0.8, 0.8, 0.8, 1.0));
+    }
+}
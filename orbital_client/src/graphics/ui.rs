@@ -102,6 +102,42 @@ impl UIButton {
     }
 }
 
+pub struct UILabel {
+    pub text: String,
+    pub padding: Vec2,
+    computed_size: Vec2,
+}
+
+impl UIElement for UILabel {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn compute_sizes(&mut self, parent_size: Option<Vec2>, rs: &mut RenderState) -> Vec2 {
+        let w = rs.get_text_width(&self.text);
+        let h = rs.get_text_height(&self.text);
+        self.computed_size = Vec2::new(w, h) + self.padding * 2.0;
+        self.computed_size
+    }
+
+    fn update_and_render(&mut self, bounds: Bounds, state: &mut State) {
+        let text_pos =
+            bounds.min + Vec2::new(self.padding.x, self.computed_size.y - self.padding.y);
+
+        state.rs.draw_text(&self.text, text_pos);
+    }
+}
+
+impl UILabel {
+    pub fn new(text: &str) -> UILabel {
+        UILabel {
+            text: text.to_string(),
+            padding: Vec2::new(7.0, 7.0),
+            computed_size: Vec2::new(0.0, 0.0),
+        }
+    }
+}
+
 pub enum UIBlockContainerXConstraint {
     None,
     LEFT,
@@ -7,6 +7,20 @@ use super::renderer::RenderState;
 
 pub type KeyCode = glfw::Key;
 
+/// The number keys above the letters, indexed by their digit.
+pub const DIGIT_KEYS: [KeyCode; 10] = [
+    KeyCode::Num0,
+    KeyCode::Num1,
+    KeyCode::Num2,
+    KeyCode::Num3,
+    KeyCode::Num4,
+    KeyCode::Num5,
+    KeyCode::Num6,
+    KeyCode::Num7,
+    KeyCode::Num8,
+    KeyCode::Num9,
+];
+
 pub struct FrameState {
     pub prev_time: f32,
     pub time: f32,
@@ -101,6 +115,7 @@ impl Window {
         window.set_cursor_enter_polling(true);
         window.set_cursor_pos_polling(true);
         window.set_mouse_button_polling(true);
+        window.set_scroll_polling(true);
 
         match cfg.window_pos {
             Some(pos) => window.set_pos(pos.x as i32, pos.y as i32),
@@ -178,6 +193,7 @@ impl Window {
         frame_input.delta_time = frame_input.time - frame_input.prev_time;
         frame_input.prev_keys = frame_input.keys;
         frame_input.prev_mouse_buttons = frame_input.mouse_buttons;
+        frame_input.mouse_scroll = 0.0;
 
         for (_, event) in glfw::flush_messages(&self.events) {
             match event {
@@ -201,6 +217,10 @@ impl Window {
                     frame_input.mouse_pos = Vec2::new(xpos as f32, ypos as f32);
                 }
 
+                glfw::WindowEvent::Scroll(xoffset, yoffset) => {
+                    frame_input.mouse_scroll += yoffset as f32;
+                }
+
                 _ => {}
             }
         }
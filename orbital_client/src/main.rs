@@ -7,7 +7,8 @@ mod audio;
 mod network;
 mod config;
 
-use config::config_parse_command_line;
+use config::{config_parse_command_line, get_config};
+use orbital_shared::replay::Replay;
 
 use crate::graphics::renderer::{RenderState};
 use crate::graphics::window::{Window};
@@ -24,7 +25,11 @@ pub struct State {
 }
 
 fn main() {
-    config_parse_command_line();
+    if let Err(e) = config_parse_command_line() {
+        println!("{}", e);
+        println!("Usage: orbital_client [-config file] [-seed n] [-replay file] [-rules file] [-server address] [-name name]");
+        std::process::exit(1);
+    }
 
     let mut window: Window = Window::new(";e");
 
@@ -42,6 +47,13 @@ fn main() {
 
     let mut gs = GameState::new(&mut state);
 
+    if let Some(path) = get_config().replay_file.clone() {
+        match Replay::load(&path) {
+            Ok(replay) => gs.start_replay(replay, &mut state),
+            Err(e) => println!("Failed to load replay {}: {}", path, e),
+        }
+    }
+
     while !window.should_close() {
         gs.update_and_render(&mut state);
         state.rs.draw_submit();
@@ -49,4 +61,6 @@ fn main() {
 
         //println!("{}", state.fs.delta_time * 1000.0)
     }
+
+    gs.save_replay();
 }
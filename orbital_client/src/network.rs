@@ -1,22 +1,281 @@
-use std::net::{TcpStream};
-use std::io::{self, Error};
+use std::fmt;
+use std::net::{Shutdown, TcpStream};
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use orbital_shared::protocol::{self, PacketReader, PacketWriter, ProtocolError};
+use orbital_shared::{
+    CreateRoomPacket, GamePacket, JoinPacket, JoinRoomPacket, RejectReason, RoomListPacket, RoomSettings,
+};
+
+/*
+   The socket belongs to a worker thread, the render loop never touches it.
+   connect starts the worker, which connects, gets into the server's lobby
+   and from then on moves packets both ways: whatever GameState sends goes
+   into a channel, and everything the server sends comes back as
+   NetworkEvents, together with changes of the connection state. poll_events
+   only drains that channel, so update_and_render never waits on the network.
+
+   In the lobby the player lists, creates and joins rooms through the
+   methods below, the answers arrive as packets like everything else. The
+   JoinAccepted for a room moves the connection from InLobby to Connected.
+*/
+
+/// How long we wait for the server to let us into its lobby before giving up.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest the worker waits for something to send before it checks the socket again.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+fn not_connected() -> ProtocolError {
+    ProtocolError::Io(Error::new(ErrorKind::NotConnected, "Not connected to a server"))
+}
+
+fn unexpected_reply(expected: &str, reply: GamePacket) -> JoinError {
+    JoinError::Protocol(ProtocolError::Io(Error::new(
+        ErrorKind::InvalidData,
+        format!("Expected {}, got {:?}", expected, reply),
+    )))
+}
+
+#[derive(Debug)]
+pub enum JoinError {
+    Protocol(ProtocolError),
+    Rejected(RejectReason),
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::Protocol(ProtocolError::VersionMismatch { .. }) => {
+                write!(f, "The server runs a different version of the game")
+            }
+            JoinError::Protocol(e) => write!(f, "{}", e),
+            JoinError::Rejected(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+impl From<ProtocolError> for JoinError {
+    fn from(e: ProtocolError) -> Self {
+        JoinError::Protocol(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    /// Connecting and joining the server, the worker is still waiting on it.
+    Connecting,
+    /// On the server but in no room yet, rooms can be listed, created and joined.
+    InLobby,
+    /// In a room, the match runs over this connection.
+    Connected,
+    /// The connection broke after the server let us in.
+    Lost,
+}
+
+/// What the worker thread reports back to the game.
+#[derive(Debug)]
+pub enum NetworkEvent {
+    StateChanged(ConnectionState),
+    /// We did not get into the lobby, the connection is closed again.
+    JoinFailed(JoinError),
+    Packet(GamePacket),
+}
+
+/// Sends `packet` and waits for the server's answer, a rejection comes back as an error.
+fn request(stream: &mut TcpStream, packet: &GamePacket) -> Result<GamePacket, JoinError> {
+    protocol::write_packet(stream, packet)?;
+
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(ProtocolError::from)?;
+    let reply = protocol::read_packet(stream);
+    stream.set_read_timeout(None).map_err(ProtocolError::from)?;
+
+    match reply? {
+        GamePacket::JoinRejected(rejected) => Err(JoinError::Rejected(rejected.reason)),
+        reply => Ok(reply),
+    }
+}
+
+/// Sends our join request, once the server let us into its lobby we get the list of rooms.
+fn join(stream: &mut TcpStream, username: &str) -> Result<RoomListPacket, JoinError> {
+    match request(stream, &GamePacket::Join(JoinPacket::new(username)))? {
+        GamePacket::RoomList(room_list) => Ok(room_list),
+        other => Err(unexpected_reply("RoomList", other)),
+    }
+}
+
+/// Moves packets between the socket and the channels until either side hangs up. Returns Ok
+/// if it was us.
+fn exchange_packets(
+    mut stream: TcpStream,
+    events: &Sender<NetworkEvent>,
+    outgoing: &Receiver<GamePacket>,
+) -> Result<(), ProtocolError> {
+    stream.set_nonblocking(true)?;
+    let mut reader = PacketReader::new();
+    let mut writer = PacketWriter::new();
+
+    loop {
+        while let Some(packet) = reader.poll(&mut stream)? {
+            if events.send(NetworkEvent::Packet(packet)).is_err() {
+                return Ok(());
+            }
+        }
+
+        // Waiting for something to send is also the pause between two looks at the socket.
+        match outgoing.recv_timeout(POLL_INTERVAL) {
+            Ok(packet) => {
+                writer.queue(&packet)?;
+                for packet in outgoing.try_iter() {
+                    writer.queue(&packet)?;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                // Whatever still fits into the socket goes out, the rest is dropped with it.
+                let _ = writer.flush(&mut stream);
+                let _ = stream.shutdown(Shutdown::Both);
+                return Ok(());
+            }
+        }
+        writer.flush(&mut stream)?;
+    }
+}
+
+fn run_worker(ip_port: String, username: String, events: Sender<NetworkEvent>, outgoing: Receiver<GamePacket>) {
+    let joined = TcpStream::connect(&ip_port)
+        .map_err(|e| JoinError::from(ProtocolError::from(e)))
+        .and_then(|mut stream| {
+            let room_list = join(&mut stream, &username)?;
+            Ok((stream, room_list))
+        });
+
+    let (stream, room_list) = match joined {
+        Ok(joined) => joined,
+        Err(e) => {
+            let _ = events.send(NetworkEvent::JoinFailed(e));
+            return;
+        }
+    };
+    let _ = events.send(NetworkEvent::StateChanged(ConnectionState::InLobby));
+    let _ = events.send(NetworkEvent::Packet(GamePacket::RoomList(room_list)));
+
+    if let Err(e) = exchange_packets(stream, &events, &outgoing) {
+        println!("Lost the connection to {}: {}", ip_port, e);
+        let _ = events.send(NetworkEvent::StateChanged(ConnectionState::Lost));
+    }
+}
 
 pub struct NetworkState {
-    stream: Option<TcpStream>,
+    connection: ConnectionState,
+    outgoing: Option<Sender<GamePacket>>,
+    events: Option<Receiver<NetworkEvent>>,
 }
 
 impl NetworkState {
     pub fn new() -> NetworkState {
-        NetworkState { stream: None }
+        NetworkState {
+            connection: ConnectionState::Disconnected,
+            outgoing: None,
+            events: None,
+        }
     }
 
-    pub fn connect_to(&mut self, ip_port: &str) -> io::Result<()> {
-        let stream = TcpStream::connect(ip_port)?;
-        self.stream = Some(stream);
-        Ok(())
+    pub fn connection_state(&self) -> ConnectionState {
+        self.connection
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection == ConnectionState::Connected
     }
-}
 
+    pub fn is_in_lobby(&self) -> bool {
+        self.connection == ConnectionState::InLobby
+    }
+
+    /// Hands `packet` to the worker, it goes out without waiting for the socket.
+    pub fn send_packet(&mut self, packet: GamePacket) -> Result<(), ProtocolError> {
+        match &self.outgoing {
+            Some(outgoing) if self.is_connected() => outgoing.send(packet).map_err(|_| not_connected()),
+            _ => Err(not_connected()),
+        }
+    }
+
+    fn send_lobby_request(&mut self, packet: GamePacket) -> Result<(), ProtocolError> {
+        match &self.outgoing {
+            Some(outgoing) if self.is_in_lobby() => outgoing.send(packet).map_err(|_| not_connected()),
+            _ => Err(not_connected()),
+        }
+    }
+
+    /// Asks for the rooms on the server, the RoomList shows up in poll_events.
+    pub fn list_rooms(&mut self) -> Result<(), ProtocolError> {
+        self.send_lobby_request(GamePacket::ListRooms)
+    }
+
+    /// Opens a room and joins it, answered with a JoinAccepted or JoinRejected in poll_events.
+    pub fn create_room(&mut self, settings: RoomSettings) -> Result<(), ProtocolError> {
+        self.send_lobby_request(GamePacket::CreateRoom(CreateRoomPacket { settings }))
+    }
+
+    /// Takes a free slot in `room_id`, answered with a JoinAccepted or JoinRejected in
+    /// poll_events. After a JoinRejected we are still in the lobby.
+    pub fn join_room(&mut self, room_id: u64) -> Result<(), ProtocolError> {
+        self.send_lobby_request(GamePacket::JoinRoom(JoinRoomPacket { room_id }))
+    }
+
+    /// Starts a worker that connects, joins the server's lobby and keeps exchanging packets from
+    /// then on. Returns right away, the first RoomList or JoinFailed shows up in poll_events.
+    pub fn connect(&mut self, ip_port: &str, username: &str) {
+        self.disconnect();
+
+        let (events, event_receiver) = channel();
+        let (outgoing, outgoing_receiver) = channel();
+        let (ip_port, username) = (ip_port.to_string(), username.to_string());
+        thread::spawn(move || run_worker(ip_port, username, events, outgoing_receiver));
+
+        self.connection = ConnectionState::Connecting;
+        self.outgoing = Some(outgoing);
+        self.events = Some(event_receiver);
+    }
+
+    /// Stops the worker, which closes the connection.
+    pub fn disconnect(&mut self) {
+        self.connection = ConnectionState::Disconnected;
+        self.outgoing = None;
+        self.events = None;
+    }
+
+    /// Everything the worker reported since the last call, never blocks.
+    pub fn poll_events(&mut self) -> Vec<NetworkEvent> {
+        let events: Vec<NetworkEvent> = match &self.events {
+            Some(events) => events.try_iter().collect(),
+            None => return Vec::new(),
+        };
+
+        for event in &events {
+            match event {
+                NetworkEvent::StateChanged(connection) => self.connection = *connection,
+                NetworkEvent::JoinFailed(_) => self.connection = ConnectionState::Disconnected,
+                // Everything after this belongs to the room.
+                NetworkEvent::Packet(GamePacket::JoinAccepted(_)) => self.connection = ConnectionState::Connected,
+                NetworkEvent::Packet(_) => {}
+            }
+        }
+        if matches!(self.connection, ConnectionState::Disconnected | ConnectionState::Lost) {
+            // The worker is gone, nothing more will come.
+            self.outgoing = None;
+            self.events = None;
+        }
+        events
+    }
+}
 
 mod tests
 {
@@ -25,9 +284,22 @@ mod tests
     #[test]
     fn test_connect_to() {
         let mut network_state = NetworkState::new();
-        match network_state.connect_to("127.0.0.1:27007") {
-            Ok(_) => println!("Connected to the server successfully!"),
-            Err(e) => println!("Failed to connect to the server: {}", e),
+        network_state.connect("127.0.0.1:27007", "test");
+        assert_eq!(network_state.connection_state(), ConnectionState::Connecting);
+
+        // Connecting happens in the background, poll until it went one way or the other.
+        let deadline = std::time::Instant::now() + HANDSHAKE_TIMEOUT * 2;
+        while network_state.connection_state() == ConnectionState::Connecting && std::time::Instant::now() < deadline {
+            for event in network_state.poll_events() {
+                match event {
+                    NetworkEvent::Packet(GamePacket::RoomList(room_list)) => {
+                        println!("Connected to the server successfully, {} rooms", room_list.rooms.len())
+                    }
+                    NetworkEvent::JoinFailed(e) => println!("Failed to connect to the server: {}", e),
+                    _ => {}
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
         }
     }
-}
\ No newline at end of file
+}
@@ -5,17 +5,12 @@ pub type Vec3 = Vector3<f32>;
 pub type Vec4 = Vector4<f32>;
 pub type Mat4 = Matrix4<f32>;
 
-pub type Vec2i = Vector2<i32>;
+pub use orbital_shared::math::Vec2i;
 
 pub fn normalized_f64_to_i16(v: f64) -> i16 {
     (v * i16::max_value() as f64).round() as i16
 }
 
-// Should be safe to use accross the network, let's hope...
-pub fn magnitude_i32(v: Vec2i) -> i32 {
-    ((v.x * v.x + v.y * v.y) as f64).sqrt().round() as i32
-}
-
 pub fn ivec_to_vec(v: Vec2i) -> Vec2 {
     Vec2::new(v.x as f32, v.y as f32)
 }
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::Sender;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use orbital_shared::map_gen::{self, MATCH_MAP_SIZE};
+use orbital_shared::rules::MatchRules;
+use orbital_shared::simulation::{PlayerSetup, Team};
+use orbital_shared::{JoinAcceptedPacket, RejectReason, RoomInfo, RoomSettings};
+
+use crate::session::SessionEvent;
+
+/*
+   The lobby keeps track of every room and who sits in which of its slots.
+   Joining happens here under the lobby lock, so two players can never get
+   the same slot. Leaving is up to the room's session thread once it noticed
+   the disconnect, the room is removed as soon as the last player left.
+*/
+
+/// A server only hosts this many rooms at once.
+pub const MAX_ROOMS: usize = 64;
+
+pub const MAX_ROOM_NAME_LENGTH: usize = 32;
+
+pub fn new_map_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+struct Room {
+    name: String,
+    map_seed: u64,
+    players: PlayerSetup,
+    rules: MatchRules,
+    /// Indexed by slot, None while nobody plays it.
+    player_names: Vec<Option<String>>,
+    started: bool,
+    events: Sender<SessionEvent>,
+}
+
+impl Room {
+    fn info(&self, room_id: u64) -> RoomInfo {
+        RoomInfo {
+            room_id,
+            name: self.name.clone(),
+            player_count: self.players.player_count() as u32,
+            players: self.player_names.iter().flatten().cloned().collect(),
+            in_progress: self.started,
+        }
+    }
+}
+
+/// The room a player just joined, `events` is where its packets go from now on.
+pub struct JoinedRoom {
+    pub accepted: JoinAcceptedPacket,
+    pub events: Sender<SessionEvent>,
+}
+
+#[derive(Default)]
+pub struct Lobby {
+    next_room_id: u64,
+    rooms: BTreeMap<u64, Room>,
+}
+
+impl Lobby {
+    pub fn new() -> Lobby {
+        Lobby::default()
+    }
+
+    pub fn room_list(&self) -> Vec<RoomInfo> {
+        self.rooms
+            .iter()
+            .map(|(room_id, room)| room.info(*room_id))
+            .collect()
+    }
+
+    /// Opens an empty room, the session running it gets its events through `events`.
+    pub fn create_room(&mut self, settings: RoomSettings, events: Sender<SessionEvent>) -> Result<u64, RejectReason> {
+        if self.rooms.len() >= MAX_ROOMS {
+            return Err(RejectReason::ServerFull);
+        }
+
+        let name = settings.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_ROOM_NAME_LENGTH {
+            return Err(RejectReason::InvalidSettings(format!(
+                "Room names need 1 to {} characters",
+                MAX_ROOM_NAME_LENGTH
+            )));
+        }
+        let players = PlayerSetup::free_for_all(settings.player_count)
+            .map_err(|e| RejectReason::InvalidSettings(e.to_string()))?;
+        settings
+            .rules
+            .validate()
+            .map_err(|e| RejectReason::InvalidSettings(e.to_string()))?;
+        // Checked up front, a room that can not get a map must never start a match.
+        let map_seed = settings.map_seed.unwrap_or_else(new_map_seed);
+        map_gen::generate_for_match(MATCH_MAP_SIZE, map_seed, settings.player_count)
+            .map_err(|e| RejectReason::InvalidSettings(e.to_string()))?;
+
+        self.next_room_id += 1;
+        let room_id = self.next_room_id;
+        self.rooms.insert(
+            room_id,
+            Room {
+                name: name.to_string(),
+                map_seed,
+                player_names: vec![None; players.player_count()],
+                players,
+                rules: settings.rules,
+                started: false,
+                events,
+            },
+        );
+        Ok(room_id)
+    }
+
+    /// Puts `username` into the first free slot of the room.
+    pub fn join(&mut self, room_id: u64, username: &str) -> Result<JoinedRoom, RejectReason> {
+        let room = self.rooms.get_mut(&room_id).ok_or(RejectReason::NoSuchRoom)?;
+        if room
+            .player_names
+            .iter()
+            .flatten()
+            .any(|name| name.eq_ignore_ascii_case(username))
+        {
+            return Err(RejectReason::NameTaken);
+        }
+
+        let slot = match room.player_names.iter().position(|name| name.is_none()) {
+            Some(slot) if !room.started => slot,
+            _ => return Err(RejectReason::RoomFull),
+        };
+        room.player_names[slot] = Some(username.to_string());
+
+        Ok(JoinedRoom {
+            accepted: JoinAcceptedPacket {
+                room_id,
+                map_seed: room.map_seed,
+                team: Team::player(slot),
+                players: room.players.clone(),
+                rules: room.rules.clone(),
+            },
+            events: room.events.clone(),
+        })
+    }
+
+    /// Called by the session once every slot is taken, nobody can join anymore.
+    pub fn start(&mut self, room_id: u64) {
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            room.started = true;
+        }
+    }
+
+    /// Frees `team`'s slot. Returns true once the room is empty and gone.
+    pub fn leave(&mut self, room_id: u64, team: Team) -> bool {
+        let room = match self.rooms.get_mut(&room_id) {
+            Some(room) => room,
+            None => return true,
+        };
+        if let Some(name) = team.index().and_then(|slot| room.player_names.get_mut(slot)) {
+            *name = None;
+        }
+
+        if room.player_names.iter().all(|name| name.is_none()) {
+            self.rooms.remove(&room_id);
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    fn settings(player_count: u32) -> RoomSettings {
+        RoomSettings {
+            name: String::from("room"),
+            player_count,
+            map_seed: Some(7),
+            rules: MatchRules::default(),
+        }
+    }
+
+    fn create(lobby: &mut Lobby, player_count: u32) -> Result<u64, RejectReason> {
+        let (events, _) = channel();
+        lobby.create_room(settings(player_count), events)
+    }
+
+    #[test]
+    fn players_fill_the_free_slots_in_order() {
+        let mut lobby = Lobby::new();
+        let room_id = create(&mut lobby, 2).unwrap();
+
+        let first = lobby.join(room_id, "first").unwrap();
+        assert_eq!(first.accepted.team, Team::player(0));
+        assert_eq!(first.accepted.map_seed, 7);
+        assert_eq!(lobby.join(room_id, "FIRST").err(), Some(RejectReason::NameTaken));
+        assert_eq!(lobby.join(room_id, "second").unwrap().accepted.team, Team::player(1));
+        assert_eq!(lobby.join(room_id, "third").err(), Some(RejectReason::RoomFull));
+
+        // A slot freed before the match started can be taken again.
+        assert!(!lobby.leave(room_id, Team::player(0)));
+        assert_eq!(lobby.join(room_id, "third").unwrap().accepted.team, Team::player(0));
+
+        lobby.start(room_id);
+        assert!(!lobby.leave(room_id, Team::player(0)));
+        assert_eq!(lobby.join(room_id, "fourth").err(), Some(RejectReason::RoomFull));
+        assert!(lobby.room_list()[0].in_progress);
+    }
+
+    #[test]
+    fn empty_rooms_are_removed() {
+        let mut lobby = Lobby::new();
+        let first = create(&mut lobby, 2).unwrap();
+        let second = create(&mut lobby, 3).unwrap();
+        assert_ne!(first, second);
+
+        lobby.join(first, "player").unwrap();
+        assert!(lobby.leave(first, Team::player(0)));
+        assert_eq!(lobby.join(first, "player").err(), Some(RejectReason::NoSuchRoom));
+
+        let rooms = lobby.room_list();
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].room_id, second);
+        assert!(rooms[0].is_open());
+    }
+
+    #[test]
+    fn bad_settings_are_rejected() {
+        let mut lobby = Lobby::new();
+        assert!(matches!(create(&mut lobby, 1), Err(RejectReason::InvalidSettings(_))));
+
+        let (events, _) = channel();
+        let mut unnamed = settings(2);
+        unnamed.name = String::from("  ");
+        assert!(matches!(
+            lobby.create_room(unnamed, events),
+            Err(RejectReason::InvalidSettings(_))
+        ));
+        assert!(lobby.room_list().is_empty());
+    }
+}
@@ -1,80 +1,181 @@
-use orbital_shared::GamePacket;
-use std::io::{Read, Write};
+mod lobby;
+mod session;
+
+use lobby::{JoinedRoom, Lobby};
+use orbital_shared::protocol::{self, ProtocolError, PROTOCOL_VERSION};
+use orbital_shared::{
+    GamePacket, JoinPacket, JoinRejectedPacket, RejectReason, RoomListPacket, RoomSettings, BUILD_HASH,
+    GAME_VERSION,
+};
+use session::{Session, SessionEvent};
+use std::io::Write;
 use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
-struct GameSession {
-    player_a_stream: Option<TcpStream>,
-    player_b_stream: Option<TcpStream>,
-}
+/// Clients that connect but never say who they are get dropped after this.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 fn main() {
     let listener = TcpListener::bind("127.0.0.1:27007").unwrap();
-
-    let game = Arc::new(Mutex::new(GameSession {
-        player_a_stream: None,
-        player_b_stream: None,
-    }));
+    let lobby = Arc::new(Mutex::new(Lobby::new()));
 
     for stream in listener.incoming() {
         println!("Connection established!");
         let stream = stream.unwrap();
 
-        let game = Arc::clone(&game);
-        thread::spawn(move || handle_connection(game, stream));
+        let lobby = Arc::clone(&lobby);
+        thread::spawn(move || handle_connection(lobby, stream));
     }
 }
 
-fn handle_connection(game: Arc<Mutex<GameSession>>, stream: TcpStream) {
-    let mut game = game.lock().unwrap();
-
-    if game.player_a_stream.is_none() {
-        game.player_a_stream = Some(stream.try_clone().unwrap());
-    } else if game.player_b_stream.is_none() {
-        game.player_b_stream = Some(stream.try_clone().unwrap());
+fn send_room_list(stream: &mut TcpStream, lobby: &Mutex<Lobby>) -> Result<(), ProtocolError> {
+    let rooms = lobby.lock().unwrap().room_list();
+    protocol::write_packet(stream, &GamePacket::RoomList(RoomListPacket { rooms }))
+}
 
-        let player_a_stream = game.player_a_stream.as_ref().unwrap().try_clone().unwrap();
-        let player_b_stream = game.player_b_stream.as_ref().unwrap().try_clone().unwrap();
+/// Opens a room for `settings` with its session running and puts its creator in.
+fn create_room(lobby: &Arc<Mutex<Lobby>>, settings: RoomSettings, username: &str) -> Result<JoinedRoom, RejectReason> {
+    let (events, session_events) = channel();
+    // Locked across both steps so nobody can take the creator's slot in between.
+    let mut locked = lobby.lock().unwrap();
+    let room_id = locked.create_room(settings, events)?;
+    let joined = locked.join(room_id, username)?;
+    drop(locked);
 
-        let (tx_a, rx_a) = std::sync::mpsc::channel::<Vec<u8>>();
-        let (tx_b, rx_b) = std::sync::mpsc::channel::<Vec<u8>>();
+    let accepted = &joined.accepted;
+    let session = Session::new(
+        room_id,
+        accepted.map_seed,
+        accepted.players.clone(),
+        accepted.rules.clone(),
+        Arc::clone(lobby),
+    );
+    thread::spawn(move || session.run(session_events));
+    println!("{} opened room {}", username, room_id);
+    Ok(joined)
+}
 
-        thread::spawn(move || relay_packets(player_a_stream, tx_b));
-        thread::spawn(move || relay_packets(player_b_stream, tx_a));
+fn reject(stream: &mut TcpStream, reason: RejectReason) {
+    println!("Rejecting player: {}", reason);
+    let packet = GamePacket::JoinRejected(JoinRejectedPacket { reason });
+    if let Err(e) = protocol::write_packet(stream, &packet) {
+        println!("Failed to send rejection: {}", e);
+    }
+}
 
-        //thread::spawn(move || send_packets(game.player_a_stream.as_mut().unwrap(), rx_a));
-        // thread::spawn(move || send_packets(game.player_b_stream.as_mut().unwrap(), rx_b));
+fn version_mismatch() -> RejectReason {
+    RejectReason::VersionMismatch {
+        game_version: GAME_VERSION.to_string(),
+        build_hash: BUILD_HASH.to_string(),
     }
 }
 
-fn relay_packets(mut stream: TcpStream, tx: std::sync::mpsc::Sender<Vec<u8>>) {
-    loop {
-        let mut buffer = [0; 1024];
-        let read_result = stream.read(&mut buffer);
-        match read_result {
-            Ok(bytes_read) => {
-                if bytes_read > 0 {
-                    let packet = buffer[..bytes_read].to_vec();
-                    tx.send(packet).unwrap();
-                } else {
-                    break;
-                }
+/// Waits for the client's Join, anything that is not a join from the same build is turned away.
+fn read_join(stream: &mut TcpStream) -> Option<JoinPacket> {
+    let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+    let join = match protocol::read_packet(stream) {
+        Ok(GamePacket::Join(join)) if join.is_compatible() => Some(join),
+        Ok(GamePacket::Join(_)) => {
+            reject(stream, version_mismatch());
+            None
+        }
+        // Our packets mean nothing to a client on another protocol, it only reads a rejection frame.
+        Err(ProtocolError::VersionMismatch { theirs, .. }) => {
+            println!("Rejecting player on protocol version {}", theirs);
+            let reason = format!(
+                "The server runs version {} ({}) with protocol {}",
+                GAME_VERSION, BUILD_HASH, PROTOCOL_VERSION
+            );
+            if let Err(e) = protocol::write_rejection(stream, &reason) {
+                println!("Failed to send rejection: {}", e);
             }
-            Err(_) => break,
+            None
         }
-    }
+        Ok(other) => {
+            println!("Expected a join, got {:?}", other);
+            None
+        }
+        Err(e) => {
+            println!("Handshake failed: {}", e);
+            None
+        }
+    };
+    let _ = stream.set_read_timeout(None);
+    join
 }
 
-fn send_packets(stream: &mut TcpStream, rx: std::sync::mpsc::Receiver<Vec<u8>>) {
-    loop {
-        match rx.recv() {
-            Ok(packet) => {
-                if let Err(_) = stream.write_all(&packet) {
-                    break;
+/// Answers lobby requests until the player got into a room, then hands the connection to it.
+fn handle_connection(lobby: Arc<Mutex<Lobby>>, mut stream: TcpStream) {
+    let join = match read_join(&mut stream) {
+        Some(join) => join,
+        None => return,
+    };
+    if send_room_list(&mut stream, &lobby).is_err() {
+        return;
+    }
+
+    let joined = loop {
+        let packet = match protocol::read_packet(&mut stream) {
+            Ok(packet) => packet,
+            Err(ProtocolError::Closed) => return,
+            Err(e) => {
+                println!("Dropping {} in the lobby: {}", join.username, e);
+                return;
+            }
+        };
+
+        let joined = match packet {
+            GamePacket::ListRooms => {
+                if send_room_list(&mut stream, &lobby).is_err() {
+                    return;
                 }
+                continue;
+            }
+            GamePacket::CreateRoom(create) => create_room(&lobby, create.settings, &join.username),
+            GamePacket::JoinRoom(join_room) => lobby.lock().unwrap().join(join_room.room_id, &join.username),
+            other => {
+                println!("Ignoring {:?} from {} in the lobby", other, join.username);
+                continue;
             }
-            Err(_) => break,
+        };
+
+        match joined {
+            Ok(joined) => break joined,
+            // Turned away from the room, not from the server, the player can pick another one.
+            Err(reason) => reject(&mut stream, reason),
+        }
+    };
+
+    let team = joined.accepted.team;
+    println!("{} joined room {} as {:?}", join.username, joined.accepted.room_id, team);
+
+    let (tx, rx) = channel::<Vec<u8>>();
+    let accepted = protocol::encode_packet(&GamePacket::JoinAccepted(joined.accepted));
+    let send_stream = stream.try_clone();
+    match (accepted, send_stream) {
+        // Queued before the session knows the sender, nothing from the room can overtake it.
+        (Ok(accepted), Ok(send_stream)) => {
+            let _ = tx.send(accepted);
+            thread::spawn(move || send_packets(send_stream, rx));
+            let _ = joined.events.send(SessionEvent::Joined(team, tx));
+        }
+        _ => {
+            println!("Could not set up the connection of {}", join.username);
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+
+    // Also reports the disconnect when setting up failed, the room frees the slot either way.
+    session::read_packets(stream, team, joined.events);
+}
+
+fn send_packets(mut stream: TcpStream, rx: Receiver<Vec<u8>>) {
+    while let Ok(packet) = rx.recv() {
+        if stream.write_all(&packet).is_err() {
+            break;
         }
     }
 }
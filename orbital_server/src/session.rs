@@ -0,0 +1,286 @@
+use std::net::TcpStream;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use orbital_shared::lockstep::{TickCollector, MAX_TICKS_AHEAD};
+use orbital_shared::map_file::{MapFile, MapMetadata};
+use orbital_shared::map_gen::{self, MATCH_MAP_SIZE};
+use orbital_shared::protocol::{self, ProtocolError};
+use orbital_shared::replay::Replay;
+use orbital_shared::rules::MatchRules;
+use orbital_shared::simulation::{PlayerSetup, Team};
+use orbital_shared::{GamePacket, TickActionPacket, TickPacket};
+
+use crate::lobby::Lobby;
+
+/*
+   Every room runs a session on its own thread. Each player in it gets a
+   reader thread that turns its packets into SessionEvents. While the room
+   fills up the session only keeps the actions players already sent, once
+   every slot is taken it owns the match: it collects the actions per tick,
+   sends out every complete Tick to all players and records them into a
+   replay, which is saved once the last player left.
+*/
+
+/// How long a tick waits for the actions of players that lag behind.
+const TICK_TIMEOUT: Duration = Duration::from_secs(2);
+
+const REPLAY_DIR: &str = "replays/";
+
+pub enum SessionEvent {
+    /// A player took the slot, the session sends its packets through the sender.
+    Joined(Team, Sender<Vec<u8>>),
+    Packet(Team, Box<GamePacket>),
+    Disconnected(Team),
+}
+
+/// Everything a player sends ends up in `events`, tagged with the slot it plays.
+pub fn read_packets(mut stream: TcpStream, team: Team, events: Sender<SessionEvent>) {
+    loop {
+        let packet = match protocol::read_packet(&mut stream) {
+            Ok(packet) => packet,
+            Err(ProtocolError::Closed) => break,
+            Err(e) => {
+                println!("Dropping {:?}: {}", team, e);
+                break;
+            }
+        };
+        if events.send(SessionEvent::Packet(team, Box::new(packet))).is_err() {
+            return;
+        }
+    }
+    let _ = events.send(SessionEvent::Disconnected(team));
+}
+
+/// Sends `packet` to every player except `except`.
+fn broadcast(senders: &[Option<Sender<Vec<u8>>>], except: Option<Team>, packet: &GamePacket) {
+    // Encoded once, every player gets the same frame.
+    let frame = match protocol::encode_packet(packet) {
+        Ok(frame) => frame,
+        Err(e) => {
+            println!("Failed to encode packet: {}", e);
+            return;
+        }
+    };
+
+    for (slot, sender) in senders.iter().enumerate() {
+        if let Some(sender) = sender {
+            if Some(Team::player(slot)) != except {
+                let _ = sender.send(frame.clone());
+            }
+        }
+    }
+}
+
+/// The replay of a match on the random map for `map_seed`, the same one the clients generate.
+fn new_recording(map_seed: u64, players: &PlayerSetup, rules: &MatchRules) -> Replay {
+    let player_count = players.player_count() as u32;
+    let worlds = map_gen::generate_for_match(MATCH_MAP_SIZE, map_seed, player_count)
+        .expect("The lobby only opens rooms whose map can be generated");
+    let metadata = MapMetadata {
+        name: format!("Random {}", map_seed),
+        author: String::from("map_gen"),
+        player_count,
+    };
+    let map = MapFile::from_worlds(metadata, MATCH_MAP_SIZE, &worlds);
+    Replay::new(map, Some(map_seed), players.clone(), rules.clone())
+}
+
+fn save_replay(room_id: u64, replay: &Replay) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = format!("{}server_{}_{}.orep", REPLAY_DIR, room_id, timestamp);
+
+    match replay.save(&path) {
+        Ok(_) => println!("Replay of {} ticks saved to {}", replay.tick_count(), path),
+        Err(e) => println!("Failed to save replay: {}", e),
+    }
+}
+
+struct Match {
+    collector: TickCollector,
+    recording: Replay,
+}
+
+/// One room, from the first player joining until the last one left.
+pub struct Session {
+    room_id: u64,
+    map_seed: u64,
+    players: PlayerSetup,
+    rules: MatchRules,
+    lobby: Arc<Mutex<Lobby>>,
+    /// Indexed by slot, None while nobody plays it.
+    senders: Vec<Option<Sender<Vec<u8>>>>,
+    /// Actions sent while the room was still filling up, handed to the collector on start. At most
+    /// one per player and tick, and only for the ticks the collector will take on start.
+    early_actions: Vec<(Team, TickActionPacket)>,
+    running: Option<Match>,
+}
+
+impl Session {
+    pub fn new(
+        room_id: u64,
+        map_seed: u64,
+        players: PlayerSetup,
+        rules: MatchRules,
+        lobby: Arc<Mutex<Lobby>>,
+    ) -> Session {
+        Session {
+            room_id,
+            map_seed,
+            senders: vec![None; players.player_count()],
+            players,
+            rules,
+            lobby,
+            early_actions: Vec::new(),
+            running: None,
+        }
+    }
+
+    fn start_match(&mut self) {
+        println!("Room {} is full, starting the match", self.room_id);
+        self.lobby.lock().unwrap().start(self.room_id);
+
+        let mut running = Match {
+            collector: TickCollector::new(self.players.player_count(), TICK_TIMEOUT),
+            recording: new_recording(self.map_seed, &self.players, &self.rules),
+        };
+        let now = Instant::now();
+        for (team, tick_action) in self.early_actions.drain(..) {
+            running
+                .collector
+                .add_actions(team, tick_action.tick_number, tick_action.actions, now);
+        }
+        self.running = Some(running);
+    }
+
+    fn add_early_actions(&mut self, team: Team, tick_action: TickActionPacket) {
+        let tick_number = tick_action.tick_number;
+        let taken = self
+            .early_actions
+            .iter()
+            .any(|(from, early)| *from == team && early.tick_number == tick_number);
+        if !(0..=MAX_TICKS_AHEAD).contains(&tick_number) || taken {
+            println!("Dropping early actions of {:?} for tick {}", team, tick_number);
+            return;
+        }
+        self.early_actions.push((team, tick_action));
+    }
+
+    fn add_actions(&mut self, team: Team, mut tick_action: TickActionPacket) {
+        // The slot comes from the connection, a client can only ever act for itself.
+        tick_action.team = team;
+        match &mut self.running {
+            Some(running) => {
+                if !running.collector.add_actions(
+                    team,
+                    tick_action.tick_number,
+                    tick_action.actions.clone(),
+                    Instant::now(),
+                ) {
+                    println!(
+                        "Dropping actions of {:?} for tick {}, waiting for tick {}",
+                        team,
+                        tick_action.tick_number,
+                        running.collector.next_tick()
+                    );
+                }
+                // The others still need the checksum in it to find desyncs.
+                broadcast(&self.senders, Some(team), &GamePacket::TickAction(tick_action));
+            }
+            None => self.add_early_actions(team, tick_action),
+        }
+    }
+
+    fn handle_packet(&mut self, team: Team, packet: GamePacket) {
+        match packet {
+            GamePacket::TickAction(tick_action) => self.add_actions(team, tick_action),
+            GamePacket::DesyncState(desync_state) => {
+                broadcast(&self.senders, Some(team), &GamePacket::DesyncState(desync_state));
+            }
+            GamePacket::MatchResult(match_result) => {
+                let result = &match_result.result;
+                if result.winners.is_empty() {
+                    println!(
+                        "Room {}: match over on tick {}, draw by {:?}",
+                        self.room_id, result.tick_number, result.reason
+                    );
+                } else {
+                    println!(
+                        "Room {}: match over on tick {}, {:?} won by {:?}",
+                        self.room_id, result.tick_number, result.winners, result.reason
+                    );
+                }
+            }
+            other => println!("Ignoring {:?} from {:?}", other, team),
+        }
+    }
+
+    /// Returns false once the room is empty and the session is done.
+    fn handle_event(&mut self, event: SessionEvent) -> bool {
+        match event {
+            SessionEvent::Joined(team, sender) => {
+                if let Some(slot) = team.index().and_then(|slot| self.senders.get_mut(slot)) {
+                    *slot = Some(sender);
+                }
+                if self.running.is_none() && self.senders.iter().all(|sender| sender.is_some()) {
+                    self.start_match();
+                }
+            }
+            SessionEvent::Packet(team, packet) => self.handle_packet(team, *packet),
+            SessionEvent::Disconnected(team) => {
+                println!("{:?} left room {}", team, self.room_id);
+                match &mut self.running {
+                    // Slots of a running match stay taken, the collector just stops waiting for them.
+                    Some(running) => running.collector.disconnect(team),
+                    None => {
+                        if let Some(slot) = team.index().and_then(|slot| self.senders.get_mut(slot)) {
+                            *slot = None;
+                        }
+                        self.early_actions.retain(|(from, _)| *from != team);
+                    }
+                }
+                if self.lobby.lock().unwrap().leave(self.room_id, team) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Runs the room until every player left.
+    pub fn run(mut self, events: Receiver<SessionEvent>) {
+        loop {
+            let deadline = self.running.as_ref().and_then(|running| running.collector.deadline());
+            let event = match deadline {
+                Some(deadline) => events.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match event {
+                Ok(event) => {
+                    if !self.handle_event(event) {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if let Some(running) = &mut self.running {
+                for tick in running.collector.ready_ticks(Instant::now()) {
+                    running.recording.record_tick(&tick);
+                    broadcast(&self.senders, None, &GamePacket::Tick(TickPacket { tick }));
+                }
+            }
+        }
+
+        println!("Room {} closed", self.room_id);
+        if let Some(running) = &self.running {
+            save_replay(self.room_id, &running.recording);
+        }
+    }
+}
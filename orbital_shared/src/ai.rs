@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+
+use crate::controller::PlayerController;
+use crate::math::*;
+use crate::simulation::*;
+
+/*
+   Built in bots. They only use integer math and iterate worlds in id order,
+   so the same match always plays out the same way, which the tournament
+   runner and replays rely on.
+
+   Bots think every THINK_INTERVAL_TICKS ticks, staggered by player so they do
+   not all act on the same tick.
+*/
+
+const THINK_INTERVAL_TICKS: i32 = 12;
+
+/// Worlds with fewer ships than this are left alone.
+const MIN_SOURCE_SHIPS: i32 = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AiKind {
+    /// Takes whatever it can beat that is closest.
+    GreedyNearest,
+    /// Reinforces threatened worlds first and only attacks with spare ships.
+    Defensive,
+    /// Throws everything at the nearest enemy player.
+    Rush,
+}
+
+impl AiKind {
+    pub const ALL: [AiKind; 3] = [AiKind::GreedyNearest, AiKind::Defensive, AiKind::Rush];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AiKind::GreedyNearest => "Greedy",
+            AiKind::Defensive => "Defensive",
+            AiKind::Rush => "Rush",
+        }
+    }
+
+    pub fn create(&self) -> Box<dyn PlayerController + Send> {
+        match self {
+            AiKind::GreedyNearest => Box::new(GreedyNearestAi),
+            AiKind::Defensive => Box::new(DefensiveAi),
+            AiKind::Rush => Box::new(RushAi),
+        }
+    }
+}
+
+fn is_thinking_tick(team: Team, tick_number: i32) -> bool {
+    let offset = team.index().unwrap_or(0) as i32;
+    (tick_number + offset) % THINK_INTERVAL_TICKS == 0
+}
+
+fn distance(a: &World, b: &World) -> i32 {
+    magnitude_i32(a.pos - b.pos)
+}
+
+fn own_worlds(sim: &Simulation, team: Team) -> Vec<&World> {
+    sim.worlds().iter().filter(|world| world.team == team).collect()
+}
+
+fn is_friendly(sim: &Simulation, team: Team, other: Team) -> bool {
+    other == team || sim.players().are_allied(team, other)
+}
+
+/// Enemy ships on their way to `world`.
+fn incoming_threat(sim: &Simulation, team: Team, world: &World) -> i32 {
+    sim.squadrons()
+        .iter()
+        .filter(|squadron| squadron.dest_world == world.id && !is_friendly(sim, team, squadron.team))
+        .map(|squadron| squadron.ship_count)
+        .sum()
+}
+
+fn attack(source: WorldId, target: WorldId, ratio: SendRatio) -> PlayerAction {
+    PlayerAction::Attack(PlayerActionAttack {
+        sources: vec![source],
+        target,
+        ratio,
+    })
+}
+
+/// Closest pair of an own world and a hostile world it can take when sending `ratio`.
+fn nearest_beatable(
+    sim: &Simulation,
+    team: Team,
+    ratio: SendRatio,
+    can_send: impl Fn(&World) -> bool,
+) -> Option<(WorldId, WorldId)> {
+    let mut best: Option<(i32, WorldId, WorldId)> = None;
+
+    for source in own_worlds(sim, team) {
+        if source.ship_count < MIN_SOURCE_SHIPS || !can_send(source) {
+            continue;
+        }
+
+        let ships = ratio.ships_to_send(source.ship_count);
+        for target in sim.worlds() {
+            if is_friendly(sim, team, target.team) {
+                continue;
+            }
+
+            let needed = target.ship_count + incoming_threat(sim, team, target).max(0) + 1;
+            let dist = distance(source, target);
+            if ships >= needed && best.is_none_or(|(best_dist, _, _)| dist < best_dist) {
+                best = Some((dist, source.id, target.id));
+            }
+        }
+    }
+
+    best.map(|(_, source, target)| (source, target))
+}
+
+pub struct GreedyNearestAi;
+
+impl GreedyNearestAi {
+    /// Sends everything from the closest world that can take a hostile world on its own.
+    pub fn decide(&mut self, sim: &Simulation, team: Team, tick_number: i32) -> PlayerAction {
+        if !is_thinking_tick(team, tick_number) {
+            return PlayerAction::None;
+        }
+
+        match nearest_beatable(sim, team, SendRatio::All, |_| true) {
+            Some((source, target)) => attack(source, target, SendRatio::All),
+            None => PlayerAction::None,
+        }
+    }
+}
+
+pub struct DefensiveAi;
+
+impl DefensiveAi {
+    /// Reinforces the world most in danger first, otherwise attacks with half of a full world.
+    pub fn decide(&mut self, sim: &Simulation, team: Team, tick_number: i32) -> PlayerAction {
+        if !is_thinking_tick(team, tick_number) {
+            return PlayerAction::None;
+        }
+
+        let worlds = own_worlds(sim, team);
+
+        // Help the most threatened world from the closest world that can spare half its ships.
+        let threatened = worlds
+            .iter()
+            .map(|world| (incoming_threat(sim, team, world) - world.ship_count, *world))
+            .filter(|(shortfall, _)| *shortfall >= 0)
+            .max_by_key(|(shortfall, world)| (*shortfall, std::cmp::Reverse(world.id)));
+
+        if let Some((_, threatened)) = threatened {
+            let helper = worlds
+                .iter()
+                .filter(|world| world.id != threatened.id)
+                .filter(|world| world.ship_count >= MIN_SOURCE_SHIPS)
+                .filter(|world| incoming_threat(sim, team, world) < world.ship_count / 2)
+                .min_by_key(|world| (distance(world, threatened), world.id));
+
+            if let Some(helper) = helper {
+                return attack(helper.id, threatened.id, SendRatio::Half);
+            }
+        }
+
+        // Only worlds that are full go on the offensive, and they keep half their ships at home.
+        let production = &sim.rules().production;
+        let is_full = |world: &World| world.ship_count >= production.for_size(world.size).capacity;
+        match nearest_beatable(sim, team, SendRatio::Half, is_full) {
+            Some((source, target)) => attack(source, target, SendRatio::Half),
+            None => PlayerAction::None,
+        }
+    }
+}
+
+pub struct RushAi;
+
+impl RushAi {
+    /// Sends every world with ships at the enemy world closest to its first world.
+    pub fn decide(&mut self, sim: &Simulation, team: Team, tick_number: i32) -> PlayerAction {
+        if !is_thinking_tick(team, tick_number) {
+            return PlayerAction::None;
+        }
+
+        let worlds = own_worlds(sim, team);
+        let sources: Vec<WorldId> = worlds
+            .iter()
+            .filter(|world| world.ship_count >= MIN_SOURCE_SHIPS)
+            .map(|world| world.id)
+            .collect();
+        let first = match worlds.first() {
+            Some(world) => *world,
+            None => return PlayerAction::None,
+        };
+
+        // Neutrals only matter once every enemy player is out of worlds.
+        let target = sim
+            .worlds()
+            .iter()
+            .filter(|world| !world.team.is_neutral() && !is_friendly(sim, team, world.team))
+            .min_by_key(|world| (distance(first, world), world.id))
+            .or_else(|| {
+                sim.worlds()
+                    .iter()
+                    .filter(|world| world.team.is_neutral())
+                    .min_by_key(|world| (distance(first, world), world.id))
+            });
+
+        match target {
+            Some(target) if !sources.is_empty() => PlayerAction::Attack(PlayerActionAttack {
+                sources,
+                target: target.id,
+                ratio: SendRatio::All,
+            }),
+            _ => PlayerAction::None,
+        }
+    }
+}
+
+/// Bots make at most one move per tick.
+fn only(action: PlayerAction) -> Vec<PlayerAction> {
+    match action {
+        PlayerAction::None => Vec::new(),
+        action => vec![action],
+    }
+}
+
+impl PlayerController for GreedyNearestAi {
+    fn next_actions(&mut self, sim: &Simulation, team: Team, tick_number: i32) -> Vec<PlayerAction> {
+        only(self.decide(sim, team, tick_number))
+    }
+}
+
+impl PlayerController for DefensiveAi {
+    fn next_actions(&mut self, sim: &Simulation, team: Team, tick_number: i32) -> Vec<PlayerAction> {
+        only(self.decide(sim, team, tick_number))
+    }
+}
+
+impl PlayerController for RushAi {
+    fn next_actions(&mut self, sim: &Simulation, team: Team, tick_number: i32) -> Vec<PlayerAction> {
+        only(self.decide(sim, team, tick_number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::world;
+
+    fn simulation(worlds: Vec<World>) -> Simulation {
+        Simulation::new(Vec2i::new(400, 100), worlds, PlayerSetup::free_for_all(2).unwrap())
+    }
+
+    fn attack_of(action: PlayerAction) -> PlayerActionAttack {
+        match action {
+            PlayerAction::Attack(attack) => attack,
+            other => panic!("Expected an attack, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn greedy_takes_the_nearest_world_it_can_beat() {
+        let sim = simulation(vec![
+            world(0, 0, 20, Team::player(0)),
+            world(1, 20, 25, Team::Neutral),
+            world(2, 50, 10, Team::Neutral),
+            world(3, 150, 5, Team::player(1)),
+        ]);
+
+        let attack = attack_of(GreedyNearestAi.decide(&sim, Team::player(0), 0));
+        assert_eq!(attack.sources, vec![WorldId(0)]);
+        assert_eq!(attack.target, WorldId(2));
+    }
+
+    #[test]
+    fn rush_goes_for_the_enemy_player() {
+        let sim = simulation(vec![
+            world(0, 0, 20, Team::player(0)),
+            world(1, 10, 8, Team::player(0)),
+            world(2, 20, 1, Team::Neutral),
+            world(3, 150, 30, Team::player(1)),
+        ]);
+
+        let attack = attack_of(RushAi.decide(&sim, Team::player(0), 0));
+        assert_eq!(attack.sources, vec![WorldId(0), WorldId(1)]);
+        assert_eq!(attack.target, WorldId(3));
+    }
+
+    #[test]
+    fn defensive_reinforces_a_threatened_world() {
+        let mut sim = simulation(vec![
+            world(0, 0, 5, Team::player(0)),
+            world(1, 40, 20, Team::player(0)),
+            world(2, -100, 30, Team::player(1)),
+        ]);
+        sim.handle_player_move(&attack(WorldId(2), WorldId(0), SendRatio::All), Team::player(1))
+            .unwrap();
+
+        let attack = attack_of(DefensiveAi.decide(&sim, Team::player(0), 0));
+        assert_eq!(attack.sources, vec![WorldId(1)]);
+        assert_eq!(attack.target, WorldId(0));
+        assert_eq!(attack.ratio, SendRatio::Half);
+    }
+
+    #[test]
+    fn bots_only_think_every_few_ticks() {
+        let sim = simulation(vec![
+            world(0, 0, 20, Team::player(0)),
+            world(1, 50, 20, Team::player(1)),
+        ]);
+
+        assert!(matches!(
+            RushAi.decide(&sim, Team::player(0), 1),
+            PlayerAction::None
+        ));
+        assert!(matches!(
+            RushAi.decide(&sim, Team::player(1), THINK_INTERVAL_TICKS - 1),
+            PlayerAction::Attack(_)
+        ));
+    }
+
+    #[test]
+    fn bot_matches_finish() {
+        for (a, b) in [
+            (AiKind::Rush, AiKind::GreedyNearest),
+            (AiKind::GreedyNearest, AiKind::Defensive),
+        ] {
+            let size = Vec2i::new(500, 300);
+            let mut sim = Simulation::new(
+                size,
+                crate::map_gen::generate_random(size, 11, 2),
+                PlayerSetup::free_for_all(2).unwrap(),
+            );
+            let mut controllers = [a.create(), b.create()];
+
+            for tick_number in 0..20000 {
+                let tick = crate::controller::collect_tick(&sim, tick_number, &mut controllers);
+                sim.tick(&tick);
+
+                if sim.result().is_some() {
+                    break;
+                }
+            }
+
+            assert!(sim.result().is_some(), "{:?} against {:?} never ended", a, b);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use std::cmp::Reverse;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fixed::{Fixed, FixedVec2};
+use crate::simulation::*;
+
+/*
+   Everything that lands on a world in the same tick fights at once, so the
+   order squadrons happen to be stored in never matters.
+
+   Ships are pooled per side: the world's garrison plus every squadron of the
+   owner or an ally defends, every other alliance attacks with the sum of its
+   squadrons. The biggest side wins and keeps the difference to the second
+   biggest. When the two biggest sides are equal nobody holds the world and it
+   turns neutral with no ships left.
+
+   With interception on, squadrons can also fight in space. Each squadron
+   flies a straight segment every tick, two enemy squadrons fight when their
+   segments bring them within range of each other at the same moment. That
+   check is exact integer math on the fixed point positions, so every peer
+   finds the same fights.
+*/
+
+/// One squadron reaching its destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arrival {
+    pub team: Team,
+    pub ship_count: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CombatOutcome {
+    /// Only friendly ships arrived.
+    Reinforced,
+    /// The defenders won against everyone that attacked.
+    Held,
+    Captured { previous: Team },
+    /// The strongest sides wiped each other out.
+    Neutralized { previous: Team },
+}
+
+struct Side {
+    alliance: Option<u8>,
+    ship_count: i32,
+}
+
+/// Resolves all `arrivals` on `world` for a single tick.
+pub fn resolve_arrivals(world: &mut World, arrivals: &[Arrival], players: &PlayerSetup) -> CombatOutcome {
+    let defender = players.alliance(world.team);
+    let defender_is = |team: Team| team == world.team || players.are_allied(team, world.team);
+
+    let mut sides = vec![Side {
+        alliance: defender,
+        ship_count: world.ship_count,
+    }];
+
+    for arrival in arrivals {
+        let alliance = if defender_is(arrival.team) {
+            defender
+        } else {
+            players.alliance(arrival.team)
+        };
+
+        match sides.iter_mut().find(|side| side.alliance == alliance) {
+            Some(side) => side.ship_count += arrival.ship_count,
+            None => sides.push(Side {
+                alliance,
+                ship_count: arrival.ship_count,
+            }),
+        }
+    }
+
+    if sides.len() == 1 {
+        world.ship_count = sides[0].ship_count;
+        return CombatOutcome::Reinforced;
+    }
+
+    sides.sort_by_key(|side| Reverse(side.ship_count));
+    let strongest = &sides[0];
+    let survivors = strongest.ship_count - sides[1].ship_count;
+    let previous = world.team;
+
+    if survivors == 0 {
+        world.team = Team::Neutral;
+        world.ship_count = 0;
+        return if previous.is_neutral() {
+            CombatOutcome::Held
+        } else {
+            CombatOutcome::Neutralized { previous }
+        };
+    }
+
+    world.ship_count = survivors;
+    if strongest.alliance == defender {
+        return CombatOutcome::Held;
+    }
+
+    world.team = capturing_team(arrivals, strongest.alliance, players);
+    CombatOutcome::Captured { previous }
+}
+
+/// Of an alliance that takes a world, the player who brought the most ships gets it, ties go
+/// to the lower player.
+fn capturing_team(arrivals: &[Arrival], alliance: Option<u8>, players: &PlayerSetup) -> Team {
+    let mut ships_per_team: Vec<(Team, i32)> = Vec::new();
+    for arrival in arrivals
+        .iter()
+        .filter(|arrival| players.alliance(arrival.team) == alliance)
+    {
+        match ships_per_team.iter_mut().find(|(team, _)| *team == arrival.team) {
+            Some((_, ship_count)) => *ship_count += arrival.ship_count,
+            None => ships_per_team.push((arrival.team, arrival.ship_count)),
+        }
+    }
+
+    ships_per_team.sort_by(|(team_a, ships_a), (team_b, ships_b)| {
+        ships_b.cmp(ships_a).then(team_a.cmp(team_b))
+    });
+    ships_per_team[0].0
+}
+
+/// Whether two squadrons flying from `a_from` to `a_to` and from `b_from` to `b_to` over the
+/// same tick are ever within `range` of each other.
+pub fn paths_meet(a_from: FixedVec2, a_to: FixedVec2, b_from: FixedVec2, b_to: FixedVec2, range: Fixed) -> bool {
+    // Seen from b, a starts at `offset` and moves by `motion` during the tick.
+    let offset = a_from - b_from;
+    let motion = (a_to - a_from) - (b_to - b_from);
+    let dot = |a: FixedVec2, b: FixedVec2| a.x.raw() as i128 * b.x.raw() as i128 + a.y.raw() as i128 * b.y.raw() as i128;
+
+    let range_squared = range.raw() as i128 * range.raw() as i128;
+    let start_squared = dot(offset, offset);
+    let motion_squared = dot(motion, motion);
+    let along = dot(offset, motion);
+
+    if along >= 0 || motion_squared == 0 {
+        // Moving apart, or not moving relative to each other, so the start is the closest.
+        start_squared <= range_squared
+    } else if -along >= motion_squared {
+        // Still closing in at the end of the tick.
+        dot(offset + motion, offset + motion) <= range_squared
+    } else {
+        // Closest somewhere during the tick, at along / motion_squared of the way. Multiplied
+        // through by motion_squared to stay in integers.
+        start_squared * motion_squared - along * along <= range_squared * motion_squared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec2i;
+    use crate::test_util::world;
+
+    fn arrival(player: usize, ship_count: i32) -> Arrival {
+        Arrival {
+            team: Team::player(player),
+            ship_count,
+        }
+    }
+
+    fn ffa() -> PlayerSetup {
+        PlayerSetup::free_for_all(4).unwrap()
+    }
+
+    #[test]
+    fn reinforcements_add_up() {
+        let mut target = world(0, 0, 10, Team::player(0));
+        let outcome = resolve_arrivals(&mut target, &[arrival(0, 5), arrival(0, 3)], &ffa());
+
+        assert_eq!(outcome, CombatOutcome::Reinforced);
+        assert_eq!(target.ship_count, 18);
+        assert_eq!(target.team, Team::player(0));
+    }
+
+    #[test]
+    fn weaker_attack_is_repelled() {
+        let mut target = world(0, 0, 10, Team::player(0));
+        let outcome = resolve_arrivals(&mut target, &[arrival(1, 7)], &ffa());
+
+        assert_eq!(outcome, CombatOutcome::Held);
+        assert_eq!(target.ship_count, 3);
+        assert_eq!(target.team, Team::player(0));
+    }
+
+    #[test]
+    fn stronger_attack_captures() {
+        let mut target = world(0, 0, 10, Team::player(0));
+        let outcome = resolve_arrivals(&mut target, &[arrival(1, 14)], &ffa());
+
+        assert_eq!(
+            outcome,
+            CombatOutcome::Captured {
+                previous: Team::player(0)
+            }
+        );
+        assert_eq!(target.ship_count, 4);
+        assert_eq!(target.team, Team::player(1));
+    }
+
+    #[test]
+    fn exact_tie_turns_the_world_neutral() {
+        let mut target = world(0, 0, 10, Team::player(0));
+        let outcome = resolve_arrivals(&mut target, &[arrival(1, 10)], &ffa());
+
+        assert_eq!(
+            outcome,
+            CombatOutcome::Neutralized {
+                previous: Team::player(0)
+            }
+        );
+        assert_eq!(target.ship_count, 0);
+        assert_eq!(target.team, Team::Neutral);
+
+        let mut target = world(0, 0, 6, Team::Neutral);
+        let outcome = resolve_arrivals(&mut target, &[arrival(2, 6)], &ffa());
+        assert_eq!(outcome, CombatOutcome::Held);
+        assert_eq!(target.team, Team::Neutral);
+        assert_eq!(target.ship_count, 0);
+    }
+
+    #[test]
+    fn reinforcement_and_attack_land_together() {
+        // Resolved in storage order the attack would capture first and then be reinforced.
+        for arrivals in [
+            [arrival(1, 15), arrival(0, 8)],
+            [arrival(0, 8), arrival(1, 15)],
+        ] {
+            let mut target = world(0, 0, 10, Team::player(0));
+            let outcome = resolve_arrivals(&mut target, &arrivals, &ffa());
+
+            assert_eq!(outcome, CombatOutcome::Held);
+            assert_eq!(target.ship_count, 3);
+            assert_eq!(target.team, Team::player(0));
+        }
+    }
+
+    #[test]
+    fn two_attackers_fight_each_other_too() {
+        for arrivals in [
+            [arrival(1, 20), arrival(2, 12)],
+            [arrival(2, 12), arrival(1, 20)],
+        ] {
+            let mut target = world(0, 0, 5, Team::Neutral);
+            let outcome = resolve_arrivals(&mut target, &arrivals, &ffa());
+
+            assert_eq!(
+                outcome,
+                CombatOutcome::Captured {
+                    previous: Team::Neutral
+                }
+            );
+            assert_eq!(target.team, Team::player(1));
+            assert_eq!(target.ship_count, 8);
+        }
+
+        // Two equally strong attackers cancel out even though both beat the garrison.
+        let mut target = world(0, 0, 5, Team::player(0));
+        let outcome = resolve_arrivals(&mut target, &[arrival(1, 12), arrival(2, 12)], &ffa());
+        assert_eq!(
+            outcome,
+            CombatOutcome::Neutralized {
+                previous: Team::player(0)
+            }
+        );
+        assert_eq!(target.team, Team::Neutral);
+    }
+
+    #[test]
+    fn allies_attack_and_defend_together() {
+        // Players 0 and 2 against 1 and 3.
+        let players = PlayerSetup::teams(4, 2).unwrap();
+
+        let mut target = world(0, 0, 10, Team::player(0));
+        let outcome = resolve_arrivals(&mut target, &[arrival(2, 5), arrival(1, 12)], &players);
+        assert_eq!(outcome, CombatOutcome::Held);
+        assert_eq!(target.team, Team::player(0));
+        assert_eq!(target.ship_count, 3);
+
+        let mut target = world(0, 0, 10, Team::player(0));
+        let outcome = resolve_arrivals(&mut target, &[arrival(1, 6), arrival(3, 9)], &players);
+        assert_eq!(
+            outcome,
+            CombatOutcome::Captured {
+                previous: Team::player(0)
+            }
+        );
+        assert_eq!(target.team, Team::player(3));
+        assert_eq!(target.ship_count, 5);
+    }
+
+    #[test]
+    fn crossing_paths_meet() {
+        let at = |x, y| FixedVec2::from_ivec(Vec2i::new(x, y));
+        let range = Fixed::from_int(1);
+
+        // Head on, they pass each other halfway through the tick.
+        assert!(paths_meet(at(0, 0), at(10, 0), at(10, 0), at(0, 0), range));
+        // Crossing the same spot at the same time.
+        assert!(paths_meet(at(0, 0), at(10, 10), at(0, 10), at(10, 0), range));
+        // Same crossing, but one of them is only half way there.
+        assert!(!paths_meet(at(0, 0), at(4, 4), at(0, 10), at(10, 0), range));
+        // Parallel, two apart.
+        assert!(!paths_meet(at(0, 0), at(10, 0), at(0, 2), at(10, 2), range));
+        assert!(paths_meet(at(0, 0), at(10, 0), at(0, 2), at(10, 2), Fixed::from_int(2)));
+        // Closing in but still apart when the tick ends.
+        assert!(!paths_meet(at(0, 0), at(3, 0), at(10, 0), at(7, 0), range));
+    }
+}
@@ -0,0 +1,40 @@
+use crate::simulation::*;
+
+/*
+   Whoever plays a slot in a match, a person at the keyboard, a peer on the
+   network or a bot, is a PlayerController. Once per tick it is asked for the
+   slot's actions and only gets to look at the simulation, never change it.
+*/
+
+pub trait PlayerController {
+    /// Everything the slot does this tick, in order. Only the first MAX_ACTIONS_PER_TICK are
+    /// used, controllers that queue input should keep the rest for the next tick.
+    fn next_actions(&mut self, sim: &Simulation, team: Team, tick_number: i32) -> Vec<PlayerAction>;
+}
+
+/// Stands in for a slot that nobody plays, for example a player that left.
+pub struct IdleController;
+
+impl PlayerController for IdleController {
+    fn next_actions(&mut self, _sim: &Simulation, _team: Team, _tick_number: i32) -> Vec<PlayerAction> {
+        Vec::new()
+    }
+}
+
+/// Builds a tick from every slot's controller, slot `n` plays `Team::player(n)`.
+pub fn collect_tick<C: PlayerController + ?Sized>(
+    sim: &Simulation,
+    tick_number: i32,
+    controllers: &mut [Box<C>],
+) -> Tick {
+    let mut tick = Tick::new(tick_number, controllers.len());
+    for (slot, controller) in controllers.iter_mut().enumerate() {
+        let team = Team::player(slot);
+        for action in controller.next_actions(sim, team, tick_number) {
+            if !tick.push_player_action(team, action) {
+                break;
+            }
+        }
+    }
+    tick
+}
@@ -0,0 +1,297 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::*;
+
+/*
+   Every peer records a checksum of its simulation after each tick and sends
+   it along with its actions. When the checksum the other side reports for a
+   tick differs from ours the match has desynced. We keep a short window of
+   snapshots and ticks around so the report can show what went wrong.
+*/
+
+pub const DEFAULT_DESYNC_HISTORY: usize = 240;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ChecksumResult {
+    Match,
+    Mismatch { local: u64, remote: u64 },
+    /// The tick is too old, or not simulated yet, so there is nothing to compare against.
+    Unknown,
+}
+
+struct TickRecord {
+    tick: Tick,
+    checksum: u64,
+    state: Simulation,
+}
+
+pub struct DesyncDetector {
+    records: VecDeque<TickRecord>,
+    history_len: usize,
+    desync_tick: Option<i32>,
+}
+
+impl DesyncDetector {
+    pub fn new(history_len: usize) -> DesyncDetector {
+        DesyncDetector {
+            records: VecDeque::with_capacity(history_len),
+            history_len,
+            desync_tick: None,
+        }
+    }
+
+    /// Call after `Simulation::tick` with the tick that was just applied.
+    pub fn record_tick(&mut self, tick: &Tick, sim: &Simulation) -> u64 {
+        let checksum = sim.checksum();
+
+        if self.records.len() == self.history_len {
+            self.records.pop_front();
+        }
+
+        self.records.push_back(TickRecord {
+            tick: tick.clone(),
+            checksum,
+            state: sim.clone(),
+        });
+
+        checksum
+    }
+
+    pub fn last_checksum(&self) -> Option<(i32, u64)> {
+        self.records
+            .back()
+            .map(|record| (record.tick.tick_number, record.checksum))
+    }
+
+    pub fn local_checksum(&self, tick_number: i32) -> Option<u64> {
+        self.record(tick_number).map(|record| record.checksum)
+    }
+
+    /// First tick a mismatch was seen on, once a match desyncs it stays desynced.
+    pub fn desync_tick(&self) -> Option<i32> {
+        self.desync_tick
+    }
+
+    pub fn check_remote(&mut self, tick_number: i32, remote: u64) -> ChecksumResult {
+        match self.local_checksum(tick_number) {
+            Some(local) if local == remote => ChecksumResult::Match,
+            Some(local) => {
+                if self.desync_tick.is_none() {
+                    self.desync_tick = Some(tick_number);
+                }
+                ChecksumResult::Mismatch { local, remote }
+            }
+            None => ChecksumResult::Unknown,
+        }
+    }
+
+    pub fn local_state(&self, tick_number: i32) -> Option<&Simulation> {
+        self.record(tick_number).map(|record| &record.state)
+    }
+
+    /// Builds a report for `tick_number`, `remote_state` is the other peer's state if it sent it.
+    pub fn report(
+        &self,
+        tick_number: i32,
+        remote_checksum: u64,
+        remote_state: Option<Simulation>,
+    ) -> Option<DesyncReport> {
+        let record = self.record(tick_number)?;
+
+        let differences = match &remote_state {
+            Some(remote_state) => diff_simulations(&record.state, remote_state),
+            None => Vec::new(),
+        };
+
+        Some(DesyncReport {
+            tick_number,
+            local_checksum: record.checksum,
+            remote_checksum,
+            local_state: record.state.clone(),
+            remote_state,
+            differences,
+            tick_history: self.records.iter().map(|record| record.tick.clone()).collect(),
+        })
+    }
+
+    fn record(&self, tick_number: i32) -> Option<&TickRecord> {
+        self.records
+            .iter()
+            .find(|record| record.tick.tick_number == tick_number)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DesyncReport {
+    pub tick_number: i32,
+    pub local_checksum: u64,
+    pub remote_checksum: u64,
+    pub local_state: Simulation,
+    pub remote_state: Option<Simulation>,
+    pub differences: Vec<String>,
+    pub tick_history: Vec<Tick>,
+}
+
+impl DesyncReport {
+    /// Writes the report as json into `dir` and returns the path of the new file.
+    pub fn write_to_dir(&self, dir: impl AsRef<Path>) -> io::Result<PathBuf> {
+        std::fs::create_dir_all(&dir)?;
+
+        let path = dir
+            .as_ref()
+            .join(format!("desync_tick_{}.json", self.tick_number));
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(&path, json)?;
+
+        Ok(path)
+    }
+}
+
+/// Human readable list of everything that differs between two states.
+pub fn diff_simulations(local: &Simulation, remote: &Simulation) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    if local.size() != remote.size() {
+        differences.push(format!(
+            "map size: local {:?}, remote {:?}",
+            local.size(),
+            remote.size()
+        ));
+    }
+
+    if local.next_squadron_id() != remote.next_squadron_id() {
+        differences.push(format!(
+            "next squadron id: local {}, remote {}",
+            local.next_squadron_id(),
+            remote.next_squadron_id()
+        ));
+    }
+
+    for world in local.worlds() {
+        match remote.world(world.id) {
+            Ok(remote_world) if remote_world != world => differences.push(format!(
+                "world {}: local {:?}, remote {:?}",
+                world.id, world, remote_world
+            )),
+            Ok(_) => {}
+            Err(_) => differences.push(format!("world {}: missing on remote", world.id)),
+        }
+    }
+
+    for world in remote.worlds() {
+        if local.world(world.id).is_err() {
+            differences.push(format!("world {}: missing on local", world.id));
+        }
+    }
+
+    for squadron in local.squadrons() {
+        match remote.squadrons().iter().find(|s| s.id == squadron.id) {
+            Some(remote_squadron) if remote_squadron != squadron => differences.push(format!(
+                "squadron {}: local {:?}, remote {:?}",
+                squadron.id, squadron, remote_squadron
+            )),
+            Some(_) => {}
+            None => differences.push(format!("squadron {}: missing on remote", squadron.id)),
+        }
+    }
+
+    for squadron in remote.squadrons() {
+        if !local.squadrons().iter().any(|s| s.id == squadron.id) {
+            differences.push(format!("squadron {}: missing on local", squadron.id));
+        }
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec2i;
+
+    fn test_simulation() -> Simulation {
+        let size = Vec2i::new(500, 300);
+        Simulation::new(
+            size,
+            crate::map_gen::generate_random(size, 3, 2),
+            PlayerSetup::free_for_all(2).unwrap(),
+        )
+    }
+
+    fn empty_tick(tick_number: i32) -> Tick {
+        Tick::new(tick_number, 2)
+    }
+
+    #[test]
+    fn matching_peers_agree() {
+        let mut local = test_simulation();
+        let mut remote = test_simulation();
+        let mut detector = DesyncDetector::new(DEFAULT_DESYNC_HISTORY);
+
+        for tick_number in 0..100 {
+            let tick = empty_tick(tick_number);
+            local.tick(&tick);
+            remote.tick(&tick);
+            detector.record_tick(&tick, &local);
+
+            assert_eq!(
+                detector.check_remote(tick_number, remote.checksum()),
+                ChecksumResult::Match
+            );
+        }
+
+        assert_eq!(detector.desync_tick(), None);
+    }
+
+    #[test]
+    fn diverged_peers_are_reported() {
+        let mut local = test_simulation();
+        let mut remote = test_simulation();
+        let mut detector = DesyncDetector::new(16);
+
+        let tick = empty_tick(1);
+        local.tick(&tick);
+        detector.record_tick(&tick, &local);
+
+        let attack = PlayerAction::Attack(PlayerActionAttack {
+            sources: vec![WorldId(0)],
+            target: WorldId(1),
+            ratio: SendRatio::All,
+        });
+        remote.handle_player_move(&attack, Team::player(0)).unwrap();
+        remote.tick(&tick);
+
+        let result = detector.check_remote(1, remote.checksum());
+        assert!(matches!(result, ChecksumResult::Mismatch { .. }));
+        assert_eq!(detector.desync_tick(), Some(1));
+
+        let report = detector.report(1, remote.checksum(), Some(remote)).unwrap();
+        assert!(report.differences.iter().any(|d| d.starts_with("world #0")));
+        assert!(report
+            .differences
+            .iter()
+            .any(|d| d.starts_with("squadron 0: missing on local")));
+        assert_eq!(report.tick_history.len(), 1);
+    }
+
+    #[test]
+    fn old_ticks_are_forgotten() {
+        let mut sim = test_simulation();
+        let mut detector = DesyncDetector::new(4);
+
+        for tick_number in 0..10 {
+            let tick = empty_tick(tick_number);
+            sim.tick(&tick);
+            detector.record_tick(&tick, &sim);
+        }
+
+        assert_eq!(detector.check_remote(2, 0), ChecksumResult::Unknown);
+        assert!(detector.local_checksum(6).is_some());
+        assert_eq!(detector.last_checksum().map(|(tick, _)| tick), Some(9));
+    }
+}
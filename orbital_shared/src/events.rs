@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+
+use crate::math::Vec2i;
+use crate::simulation::*;
+
+/*
+   While ticking the simulation records what happened as events. They are not
+   part of the simulation state, they only describe the last tick, so anything
+   that wants to react (audio, effects, stats) reads them right after
+   Simulation::tick and hands them to its listeners.
+*/
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SimEvent {
+    SquadronLaunched {
+        squadron: u32,
+        team: Team,
+        source: WorldId,
+        target: WorldId,
+        ship_count: i32,
+    },
+    SquadronArrived {
+        squadron: u32,
+        team: Team,
+        world: WorldId,
+        ship_count: i32,
+    },
+    SquadronRedirected {
+        squadron: u32,
+        team: Team,
+        target: WorldId,
+    },
+    /// Two enemy squadrons met in space and both lost `ships_lost`, the smaller one is gone.
+    SquadronsClashed {
+        first: u32,
+        first_team: Team,
+        second: u32,
+        second_team: Team,
+        ships_lost: i32,
+        pos: Vec2i,
+    },
+    WorldCaptured {
+        world: WorldId,
+        team: Team,
+        previous: Team,
+        ship_count: i32,
+    },
+    /// Friendly ships landed and nobody attacked, `ship_count` is the new garrison.
+    WorldReinforced {
+        world: WorldId,
+        team: Team,
+        ship_count: i32,
+    },
+    /// The owner fought off an attack.
+    WorldDefended {
+        world: WorldId,
+        team: Team,
+        ship_count: i32,
+    },
+    /// The two strongest sides cancelled out and the world is neutral now.
+    WorldNeutralized { world: WorldId, previous: Team },
+    PlayerEliminated { team: Team },
+    ProductionTick,
+}
+
+pub trait SimEventListener {
+    fn on_sim_event(&mut self, tick_number: i32, event: &SimEvent);
+}
+
+/// Hands every event to every listener, in order.
+pub fn dispatch(tick_number: i32, events: &[SimEvent], listeners: &mut [&mut dyn SimEventListener]) {
+    for event in events {
+        for listener in listeners.iter_mut() {
+            listener.on_sim_event(tick_number, event);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlayerStats {
+    pub squadrons_launched: u32,
+    pub ships_launched: i64,
+    pub worlds_captured: u32,
+    pub worlds_lost: u32,
+    /// Ships that died fighting other squadrons in space.
+    pub ships_lost_in_space: i64,
+    pub eliminated_on_tick: Option<i32>,
+}
+
+/// Per player totals for a match, built from its events.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MatchStats {
+    pub players: Vec<PlayerStats>,
+    pub production_ticks: u32,
+}
+
+impl MatchStats {
+    pub fn new(player_count: usize) -> MatchStats {
+        MatchStats {
+            players: vec![PlayerStats::default(); player_count],
+            production_ticks: 0,
+        }
+    }
+
+    pub fn player(&self, team: Team) -> Option<&PlayerStats> {
+        team.index().and_then(|index| self.players.get(index))
+    }
+
+    fn player_mut(&mut self, team: Team) -> Option<&mut PlayerStats> {
+        team.index().and_then(move |index| self.players.get_mut(index))
+    }
+}
+
+impl SimEventListener for MatchStats {
+    fn on_sim_event(&mut self, tick_number: i32, event: &SimEvent) {
+        match event {
+            SimEvent::SquadronLaunched {
+                team, ship_count, ..
+            } => {
+                if let Some(stats) = self.player_mut(*team) {
+                    stats.squadrons_launched += 1;
+                    stats.ships_launched += *ship_count as i64;
+                }
+            }
+            SimEvent::WorldCaptured { team, previous, .. } => {
+                if let Some(stats) = self.player_mut(*team) {
+                    stats.worlds_captured += 1;
+                }
+                if let Some(stats) = self.player_mut(*previous) {
+                    stats.worlds_lost += 1;
+                }
+            }
+            SimEvent::WorldNeutralized { previous, .. } => {
+                if let Some(stats) = self.player_mut(*previous) {
+                    stats.worlds_lost += 1;
+                }
+            }
+            SimEvent::PlayerEliminated { team } => {
+                if let Some(stats) = self.player_mut(*team) {
+                    stats.eliminated_on_tick = Some(tick_number);
+                }
+            }
+            SimEvent::SquadronsClashed {
+                first_team,
+                second_team,
+                ships_lost,
+                ..
+            } => {
+                for team in [first_team, second_team] {
+                    if let Some(stats) = self.player_mut(*team) {
+                        stats.ships_lost_in_space += *ships_lost as i64;
+                    }
+                }
+            }
+            SimEvent::ProductionTick => self.production_ticks += 1,
+            SimEvent::SquadronArrived { .. }
+            | SimEvent::SquadronRedirected { .. }
+            | SimEvent::WorldReinforced { .. }
+            | SimEvent::WorldDefended { .. } => {}
+        }
+    }
+}
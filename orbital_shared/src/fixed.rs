@@ -0,0 +1,302 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use cgmath::Vector2;
+use serde::{Deserialize, Serialize};
+
+use crate::math::Vec2i;
+
+/*
+   Lockstep needs every peer to compute the exact same bits, so the simulation
+   never touches floats. Fixed is a 48.16 fixed point number, all operations
+   are plain integer math which behaves the same on every target and at every
+   optimization level. Floats only show up in to_f32 for rendering.
+*/
+
+pub const FRACTION_BITS: u32 = 16;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Fixed(i64);
+
+impl Fixed {
+    pub const ZERO: Fixed = Fixed(0);
+    pub const ONE: Fixed = Fixed(1 << FRACTION_BITS);
+
+    pub const fn from_raw(raw: i64) -> Fixed {
+        Fixed(raw)
+    }
+
+    pub const fn from_int(v: i32) -> Fixed {
+        Fixed((v as i64) << FRACTION_BITS)
+    }
+
+    /// num / den, truncated toward zero.
+    pub fn from_ratio(num: i64, den: i64) -> Fixed {
+        Fixed((((num as i128) << FRACTION_BITS) / den as i128) as i64)
+    }
+
+    pub fn raw(self) -> i64 {
+        self.0
+    }
+
+    pub fn floor(self) -> i32 {
+        (self.0 >> FRACTION_BITS) as i32
+    }
+
+    pub fn round(self) -> i32 {
+        ((self.0 + (1 << (FRACTION_BITS - 1))) >> FRACTION_BITS) as i32
+    }
+
+    pub fn abs(self) -> Fixed {
+        Fixed(self.0.abs())
+    }
+
+    pub fn sqrt(self) -> Fixed {
+        assert!(self.0 >= 0, "Square root of a negative number");
+        Fixed(isqrt_u128((self.0 as u128) << FRACTION_BITS) as i64)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / (1i64 << FRACTION_BITS) as f32
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+    fn add(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+    fn sub(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0 - rhs.0)
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+    fn mul(self, rhs: Fixed) -> Fixed {
+        Fixed(((self.0 as i128 * rhs.0 as i128) >> FRACTION_BITS) as i64)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Fixed;
+    fn div(self, rhs: Fixed) -> Fixed {
+        Fixed((((self.0 as i128) << FRACTION_BITS) / rhs.0 as i128) as i64)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct FixedVec2 {
+    pub x: Fixed,
+    pub y: Fixed,
+}
+
+impl FixedVec2 {
+    pub const ZERO: FixedVec2 = FixedVec2 {
+        x: Fixed::ZERO,
+        y: Fixed::ZERO,
+    };
+
+    pub fn new(x: Fixed, y: Fixed) -> FixedVec2 {
+        FixedVec2 { x, y }
+    }
+
+    pub fn from_ivec(v: Vec2i) -> FixedVec2 {
+        FixedVec2::new(Fixed::from_int(v.x), Fixed::from_int(v.y))
+    }
+
+    pub fn round(self) -> Vec2i {
+        Vec2i::new(self.x.round(), self.y.round())
+    }
+
+    pub fn length(self) -> Fixed {
+        let x = self.x.0 as i128;
+        let y = self.y.0 as i128;
+        Fixed(isqrt_u128((x * x + y * y) as u128) as i64)
+    }
+
+    /// a + (b - a) * num / den, exact up to the last fraction bit.
+    pub fn lerp_ratio(a: FixedVec2, b: FixedVec2, num: i64, den: i64) -> FixedVec2 {
+        let lerp = |a: Fixed, b: Fixed| {
+            Fixed(a.0 + ((b.0 - a.0) as i128 * num as i128 / den as i128) as i64)
+        };
+        FixedVec2::new(lerp(a.x, b.x), lerp(a.y, b.y))
+    }
+
+    /// Rotated counter clockwise by num / den of a full turn, see sin_cos_turns.
+    pub fn rotate_turns(self, num: i64, den: i64) -> FixedVec2 {
+        let (sin, cos) = sin_cos_turns(num, den);
+        FixedVec2::new(self.x * cos - self.y * sin, self.x * sin + self.y * cos)
+    }
+
+    pub fn to_vec2(self) -> Vector2<f32> {
+        Vector2::new(self.x.to_f32(), self.y.to_f32())
+    }
+}
+
+impl Add for FixedVec2 {
+    type Output = FixedVec2;
+    fn add(self, rhs: FixedVec2) -> FixedVec2 {
+        FixedVec2::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl Sub for FixedVec2 {
+    type Output = FixedVec2;
+    fn sub(self, rhs: FixedVec2) -> FixedVec2 {
+        FixedVec2::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl Mul<Fixed> for FixedVec2 {
+    type Output = FixedVec2;
+    fn mul(self, rhs: Fixed) -> FixedVec2 {
+        FixedVec2::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+/// Sine and cosine of num / den of a full turn, either may be negative. Computed with a Taylor
+/// series in 2.30 fixed point, so the result is the same everywhere and quarter turns are exact.
+pub fn sin_cos_turns(num: i64, den: i64) -> (Fixed, Fixed) {
+    const SCALE_BITS: u32 = 30;
+    // pi / 2 in 2.30 fixed point.
+    const HALF_PI: i128 = 1686629713;
+
+    assert!(den != 0, "A turn can not be split into zero parts");
+    let (num, den) = if den < 0 { (-num, -den) } else { (num, den) };
+    let num = num.rem_euclid(den) as i128;
+    let den = den as i128;
+
+    // Reduce to the first quadrant, where the series converges quickly.
+    let quadrant = num * 4 / den;
+    let angle = HALF_PI * (num * 4 - quadrant * den) / den;
+
+    let mut sin = 0i128;
+    let mut cos = 0i128;
+    // term is angle^k / k!, the series signs go +1, +x, -x^2/2, -x^3/6, +x^4/24, ...
+    let mut term = 1i128 << SCALE_BITS;
+    for k in 0..20i128 {
+        let signed = if k % 4 < 2 { term } else { -term };
+        if k % 2 == 0 {
+            cos += signed;
+        } else {
+            sin += signed;
+        }
+        term = ((term * angle) >> SCALE_BITS) / (k + 1);
+    }
+
+    let (sin, cos) = match quadrant {
+        0 => (sin, cos),
+        1 => (cos, -sin),
+        2 => (-sin, -cos),
+        _ => (-cos, sin),
+    };
+
+    let to_fixed = |v: i128| {
+        let shift = SCALE_BITS - FRACTION_BITS;
+        Fixed(((v + (1 << (shift - 1))) >> shift) as i64)
+    };
+    (to_fixed(sin), to_fixed(cos))
+}
+
+/// Largest r with r * r <= n.
+pub fn isqrt_u128(n: u128) -> u128 {
+    let mut op = n;
+    let mut result = 0u128;
+    let mut one = 1u128 << 126;
+
+    while one > op {
+        one >>= 2;
+    }
+
+    while one != 0 {
+        if op >= result + one {
+            op -= result + one;
+            result = (result >> 1) + one;
+        } else {
+            result >>= 1;
+        }
+        one >>= 2;
+    }
+
+    result
+}
+
+pub fn isqrt(n: u64) -> u64 {
+    isqrt_u128(n as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isqrt_is_floor_of_sqrt() {
+        for n in 0..100_000u64 {
+            let r = isqrt(n);
+            assert!(r * r <= n && (r + 1) * (r + 1) > n, "isqrt({}) = {}", n, r);
+        }
+
+        assert_eq!(isqrt(u64::MAX), 4294967295);
+        assert_eq!(isqrt_u128(u128::MAX), u64::MAX as u128);
+    }
+
+    #[test]
+    fn fixed_arithmetic() {
+        let a = Fixed::from_int(3);
+        let b = Fixed::from_ratio(1, 4);
+
+        assert_eq!((a + b).raw(), 212992);
+        assert_eq!((a - b).raw(), 180224);
+        assert_eq!((a * b).raw(), 49152);
+        assert_eq!((a / b), Fixed::from_int(12));
+        assert_eq!(Fixed::from_ratio(-7, 2).floor(), -4);
+        assert_eq!(Fixed::from_ratio(-7, 2).round(), -3);
+        assert_eq!(Fixed::from_ratio(1, 3).raw(), 21845);
+        assert_eq!(Fixed::from_int(2).sqrt().raw(), 92681);
+    }
+
+    #[test]
+    fn sin_cos_matches_floats() {
+        for den in 1..=16 {
+            for num in -den..=den * 2 {
+                let (sin, cos) = sin_cos_turns(num, den);
+                let angle = std::f64::consts::TAU * num as f64 / den as f64;
+                let tolerance = 2.0 / (1i64 << FRACTION_BITS) as f64;
+                assert!((sin.to_f32() as f64 - angle.sin()).abs() < tolerance, "sin {}/{}", num, den);
+                assert!((cos.to_f32() as f64 - angle.cos()).abs() < tolerance, "cos {}/{}", num, den);
+            }
+        }
+
+        assert_eq!(sin_cos_turns(1, 4), (Fixed::ONE, Fixed::ZERO));
+        assert_eq!(sin_cos_turns(3, 4), (-Fixed::ONE, Fixed::ZERO));
+        assert_eq!(sin_cos_turns(-1, 2), (Fixed::ZERO, -Fixed::ONE));
+
+        let v = FixedVec2::from_ivec(Vec2i::new(10, -3));
+        assert_eq!(v.rotate_turns(1, 4).round(), Vec2i::new(3, 10));
+        assert_eq!(v.rotate_turns(1, 8).round(), Vec2i::new(9, 5));
+    }
+
+    #[test]
+    fn vector_length_and_lerp() {
+        let v = FixedVec2::from_ivec(Vec2i::new(30, -40));
+        assert_eq!(v.length(), Fixed::from_int(50));
+
+        let a = FixedVec2::from_ivec(Vec2i::new(0, 0));
+        let b = FixedVec2::from_ivec(Vec2i::new(10, -5));
+        let mid = FixedVec2::lerp_ratio(a, b, 1, 3);
+        assert_eq!(mid.x.raw(), 218453);
+        assert_eq!(mid.y.raw(), -109226);
+        assert_eq!(FixedVec2::lerp_ratio(a, b, 3, 3), b);
+    }
+}
@@ -1,13 +1,200 @@
+use std::fmt;
+
 use serde::{Serialize, Deserialize};
 
+use protocol::PROTOCOL_VERSION;
+use rules::MatchRules;
+use simulation::{MatchResult, PlayerAction, PlayerSetup, Simulation, Team, Tick};
+
+pub mod ai;
+pub mod combat;
+pub mod controller;
+pub mod desync;
+pub mod events;
+pub mod fixed;
+pub mod lockstep;
+pub mod map_file;
+pub mod map_gen;
+pub mod math;
+pub mod orbit;
+pub mod protocol;
+pub mod replay;
+pub mod rng;
+pub mod rules;
+pub mod simulation;
+#[cfg(test)]
+mod test_util;
+
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Identifies the exact build, release builds set ORBITAL_BUILD_HASH at compile time. Peers only
+/// play together on the same build, anything else could simulate differently and desync.
+pub const BUILD_HASH: &str = match option_env!("ORBITAL_BUILD_HASH") {
+    Some(hash) => hash,
+    None => "dev",
+};
+
+/// First packet a client sends, the server lets it into the lobby with a RoomList or answers
+/// with JoinRejected.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct JoinPacket{
     pub username: String,
+    pub protocol_version: u16,
+    pub game_version: String,
+    pub build_hash: String,
+}
+
+impl JoinPacket {
+    /// A join request from this build.
+    pub fn new(username: &str) -> JoinPacket {
+        JoinPacket {
+            username: username.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            game_version: GAME_VERSION.to_string(),
+            build_hash: BUILD_HASH.to_string(),
+        }
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version == PROTOCOL_VERSION
+            && self.game_version == GAME_VERSION
+            && self.build_hash == BUILD_HASH
+    }
+}
+
+/// What a room plays, picked by whoever creates it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoomSettings{
+    pub name: String,
+    pub player_count: u32,
+    /// The server picks a random map when this is None.
+    pub map_seed: Option<u64>,
+    pub rules: MatchRules,
+}
+
+/// A room as the lobby lists it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoomInfo{
+    pub room_id: u64,
+    pub name: String,
+    pub player_count: u32,
+    pub players: Vec<String>,
+    pub in_progress: bool,
+}
+
+impl RoomInfo {
+    /// Whether somebody can still join.
+    pub fn is_open(&self) -> bool {
+        !self.in_progress && self.players.len() < self.player_count as usize
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RoomListPacket{
+    pub rooms: Vec<RoomInfo>,
+}
+
+/// Creates a room and joins it right away, answered like a JoinRoom.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateRoomPacket{
+    pub settings: RoomSettings,
+}
+
+/// Answered with JoinAccepted, or JoinRejected and the client stays in the lobby.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JoinRoomPacket{
+    pub room_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JoinAcceptedPacket{
+    pub room_id: u64,
+    pub map_seed: u64,
+    /// The slot this client plays in.
+    pub team: Team,
+    pub players: PlayerSetup,
+    pub rules: MatchRules,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// Carries what the server runs, so the player knows what to update to.
+    VersionMismatch { game_version: String, build_hash: String },
+    ServerFull,
+    NameTaken,
+    NoSuchRoom,
+    RoomFull,
+    InvalidSettings(String),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RejectReason::VersionMismatch {
+                game_version,
+                build_hash,
+            } => write!(
+                f,
+                "The server runs version {} ({}), this is {} ({})",
+                game_version, build_hash, GAME_VERSION, BUILD_HASH
+            ),
+            RejectReason::ServerFull => write!(f, "The server is full"),
+            RejectReason::NameTaken => write!(f, "Somebody in the room already uses that name"),
+            RejectReason::NoSuchRoom => write!(f, "The room does not exist anymore"),
+            RejectReason::RoomFull => write!(f, "The room is full or already playing"),
+            RejectReason::InvalidSettings(e) => write!(f, "Invalid room settings: {}", e),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JoinRejectedPacket{
+    pub reason: RejectReason,
+}
+
+/// A player's actions for `tick_number`, together with the checksum of their state after `checksum_tick`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TickActionPacket{
+    pub tick_number: i32,
+    pub team: Team,
+    pub actions: Vec<PlayerAction>,
+    pub checksum_tick: i32,
+    pub checksum: u64,
+}
+
+/// The actions of every player for one tick, the server sends it to everyone once they are all
+/// in. Online clients only ever simulate these.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TickPacket{
+    pub tick: Tick,
+}
+
+/// Sent once a desync is detected so the other side can put both states in its report.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DesyncStatePacket{
+    pub tick_number: i32,
+    pub checksum: u64,
+    pub state: Simulation,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MatchResultPacket{
+    pub result: MatchResult,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum GamePacket {
     Join(JoinPacket),
+    JoinAccepted(JoinAcceptedPacket),
+    JoinRejected(JoinRejectedPacket),
+    ListRooms,
+    RoomList(RoomListPacket),
+    CreateRoom(CreateRoomPacket),
+    JoinRoom(JoinRoomPacket),
+    TickAction(TickActionPacket),
+    Tick(TickPacket),
+    DesyncState(DesyncStatePacket),
+    MatchResult(MatchResultPacket),
     Leave,
     Move,
     Shoot,
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::simulation::*;
+
+/*
+   Online matches run in lockstep through the server. Clients never simulate
+   their own input right away, they send it to the server for a tick a few
+   ticks ahead (INPUT_DELAY_TICKS) and only simulate ticks the server sent
+   back. The server waits until every player's actions for a tick are in and
+   then sends the same combined Tick to everyone, so all clients simulate
+   exactly the same thing.
+
+   The delay hides the round trip: while tick N is simulated the actions for
+   N + INPUT_DELAY_TICKS are already on their way. The first few ticks have
+   nothing to wait for, clients send empty actions for them right away.
+
+   A player that stops sending must not freeze the match for everyone else,
+   once somebody's actions for a tick are in the others get `timeout` to
+   follow, after that the tick goes out without them.
+*/
+
+pub const INPUT_DELAY_TICKS: i32 = 3;
+
+/// Actions further ahead than this are dropped, a client can not make the server hold on to
+/// an unbounded number of ticks.
+pub const MAX_TICKS_AHEAD: i32 = 64;
+
+struct PendingTick {
+    actions: Vec<Option<Vec<PlayerAction>>>,
+    first_arrival: Instant,
+}
+
+/// Collects every player's actions per tick on the server and hands out complete ticks in order.
+pub struct TickCollector {
+    timeout: Duration,
+    next_tick: i32,
+    connected: Vec<bool>,
+    pending: BTreeMap<i32, PendingTick>,
+}
+
+impl TickCollector {
+    pub fn new(player_count: usize, timeout: Duration) -> TickCollector {
+        TickCollector {
+            timeout,
+            next_tick: 0,
+            connected: vec![true; player_count],
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// The tick the collector is waiting for.
+    pub fn next_tick(&self) -> i32 {
+        self.next_tick
+    }
+
+    /// Stores `team`'s actions for `tick_number`. Returns false and drops them if the tick
+    /// already went out, is too far ahead or the player already sent actions for it.
+    pub fn add_actions(&mut self, team: Team, tick_number: i32, actions: Vec<PlayerAction>, now: Instant) -> bool {
+        let slot = match team.index() {
+            Some(slot) if slot < self.connected.len() => slot,
+            _ => return false,
+        };
+        if tick_number < self.next_tick || tick_number > self.next_tick + MAX_TICKS_AHEAD {
+            return false;
+        }
+
+        let player_count = self.connected.len();
+        let pending = self.pending.entry(tick_number).or_insert_with(|| PendingTick {
+            actions: vec![None; player_count],
+            first_arrival: now,
+        });
+        if pending.actions[slot].is_some() {
+            return false;
+        }
+
+        pending.actions[slot] = Some(actions);
+        true
+    }
+
+    /// Stops waiting for `team`, its slot plays no actions from now on.
+    pub fn disconnect(&mut self, team: Team) {
+        if let Some(connected) = team.index().and_then(|slot| self.connected.get_mut(slot)) {
+            *connected = false;
+        }
+    }
+
+    pub fn is_anyone_connected(&self) -> bool {
+        self.connected.iter().any(|connected| *connected)
+    }
+
+    /// When the tick being waited for goes out even if somebody is missing.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending
+            .get(&self.next_tick)
+            .map(|pending| pending.first_arrival + self.timeout)
+    }
+
+    fn is_complete(&self, pending: &PendingTick) -> bool {
+        pending
+            .actions
+            .iter()
+            .zip(&self.connected)
+            .all(|(actions, connected)| actions.is_some() || !connected)
+    }
+
+    /// Every tick that is ready to be sent, in order. A tick is ready once every connected
+    /// player sent their actions or its deadline passed.
+    pub fn ready_ticks(&mut self, now: Instant) -> Vec<Tick> {
+        let mut ready = Vec::new();
+        loop {
+            let is_ready = match self.pending.get(&self.next_tick) {
+                Some(pending) => self.is_complete(pending) || now >= pending.first_arrival + self.timeout,
+                None => false,
+            };
+            if !is_ready {
+                break;
+            }
+
+            let pending = self.pending.remove(&self.next_tick).expect("Checked above");
+            let mut tick = Tick::new(self.next_tick, self.connected.len());
+            for (slot, actions) in pending.actions.into_iter().enumerate() {
+                for action in actions.unwrap_or_default() {
+                    if !tick.push_player_action(Team::player(slot), action) {
+                        break;
+                    }
+                }
+            }
+            ready.push(tick);
+            self.next_tick += 1;
+        }
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn surrender() -> Vec<PlayerAction> {
+        vec![PlayerAction::Surrender]
+    }
+
+    #[test]
+    fn ticks_go_out_once_everyone_sent_their_actions() {
+        let now = Instant::now();
+        let mut collector = TickCollector::new(2, TIMEOUT);
+
+        assert!(collector.add_actions(Team::player(1), 1, surrender(), now));
+        assert!(collector.add_actions(Team::player(0), 0, Vec::new(), now));
+        assert!(collector.ready_ticks(now).is_empty());
+
+        assert!(collector.add_actions(Team::player(1), 0, Vec::new(), now));
+        assert!(collector.add_actions(Team::player(0), 1, Vec::new(), now));
+        let ticks = collector.ready_ticks(now);
+        assert_eq!(ticks.iter().map(|tick| tick.tick_number).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(ticks[1].player_actions(Team::player(1)).len(), 1);
+        assert_eq!(collector.next_tick(), 2);
+
+        // Too late, too early or twice.
+        assert!(!collector.add_actions(Team::player(0), 1, Vec::new(), now));
+        assert!(!collector.add_actions(Team::player(0), 2 + MAX_TICKS_AHEAD + 1, Vec::new(), now));
+        assert!(collector.add_actions(Team::player(0), 2, Vec::new(), now));
+        assert!(!collector.add_actions(Team::player(0), 2, surrender(), now));
+    }
+
+    #[test]
+    fn missing_players_are_waited_for_until_the_timeout() {
+        let now = Instant::now();
+        let mut collector = TickCollector::new(2, TIMEOUT);
+
+        collector.add_actions(Team::player(0), 0, surrender(), now);
+        assert_eq!(collector.deadline(), Some(now + TIMEOUT));
+        assert!(collector.ready_ticks(now + TIMEOUT / 2).is_empty());
+
+        let ticks = collector.ready_ticks(now + TIMEOUT);
+        assert_eq!(ticks.len(), 1);
+        assert!(ticks[0].player_actions(Team::player(1)).is_empty());
+
+        // Once a player is gone nobody waits for it anymore.
+        collector.disconnect(Team::player(1));
+        collector.add_actions(Team::player(0), 1, Vec::new(), now);
+        assert_eq!(collector.ready_ticks(now).len(), 1);
+    }
+}
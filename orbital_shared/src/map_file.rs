@@ -0,0 +1,508 @@
+use std::fmt;
+use std::path::Path;
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::math::*;
+use crate::orbit::{self, Orbit};
+use crate::simulation::*;
+
+/*
+   On-disk map format.
+
+   Maps are stored either as JSON, meant to be written and tweaked by hand,
+   or as a compact bincode blob. Both hold the same MapFile. A JSON map looks
+   like this:
+
+   {
+       "version": 3,
+       "metadata": { "name": "Twin Suns", "author": "Dec", "player_count": 2 },
+       "size": { "x": 500, "y": 300 },
+       "worlds": [
+           { "pos": { "x": -200, "y": 0 }, "size": "Large", "owner": { "Player": 0 }, "ships": 30 },
+           { "pos": { "x": 0, "y": 0 }, "size": "Small", "owner": "Neutral", "ships": 10 },
+           { "pos": { "x": 200, "y": 0 }, "size": "Large", "owner": { "Player": 1 }, "ships": 30 },
+           { "pos": { "x": 0, "y": 60 }, "size": "Small", "owner": "Neutral", "ships": 5,
+             "orbit": { "center": { "World": 1 }, "offset": { "x": 0, "y": 60 }, "period_ticks": 480 } }
+       ]
+   }
+
+   size is the full extent of the map, centered on the origin, and every
+   world has to lie inside it. ships is the starting garrison. owner is either
+   "Neutral" or one of the players, counted from 0 up to player_count. Worlds
+   get their WorldId from their place in the list, the first world is #0.
+   orbit is optional, an orbiting world circles either
+   { "Point": { "x": 0, "y": 0 } } or { "World": id } and its pos is only
+   where it shows up in editors, see Orbit. The bincode variant is the bytes
+   OMAP, the version as a little endian u32 and then the bincode encoded
+   MapFile, which may not take more than MAX_BINARY_MAP_SIZE bytes.
+
+   version is bumped whenever the layout changes, files with another version
+   are rejected instead of being half read.
+*/
+
+pub const MAP_FILE_VERSION: u32 = 3;
+
+const BINARY_MAGIC: &[u8; 4] = b"OMAP";
+
+/// Binary maps are decoded with this limit, so a broken length in the file can not make us
+/// allocate more than that.
+pub const MAX_BINARY_MAP_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum MapFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Bincode(bincode::Error),
+    NotABinaryMap,
+    UnsupportedVersion { found: u32, expected: u32 },
+    InvalidSize(Vec2i),
+    InvalidPlayerCount(u32),
+    NoWorlds,
+    WorldOutOfBounds { index: usize, pos: Vec2i },
+    NegativeShips { index: usize, ships: i32 },
+    InvalidOwner { index: usize, owner: Team },
+    /// The orbit has no period, or circles a missing world or itself.
+    InvalidOrbit { index: usize },
+}
+
+impl fmt::Display for MapFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapFileError::Io(e) => write!(f, "Failed to read or write map file: {}", e),
+            MapFileError::Json(e) => write!(f, "Malformed map json: {}", e),
+            MapFileError::Bincode(e) => write!(f, "Malformed binary map: {}", e),
+            MapFileError::NotABinaryMap => write!(f, "File is not a binary map, missing OMAP header"),
+            MapFileError::UnsupportedVersion { found, expected } => write!(
+                f,
+                "Map file version {} is not supported, expected version {}",
+                found, expected
+            ),
+            MapFileError::InvalidSize(size) => {
+                write!(f, "Map size must be positive, got {}x{}", size.x, size.y)
+            }
+            MapFileError::InvalidPlayerCount(count) => write!(
+                f,
+                "Player count must be between {} and {}, got {}",
+                MIN_PLAYERS, MAX_PLAYERS, count
+            ),
+            MapFileError::NoWorlds => write!(f, "Map has no worlds"),
+            MapFileError::WorldOutOfBounds { index, pos } => write!(
+                f,
+                "World {} at ({}, {}) lies outside the map",
+                index, pos.x, pos.y
+            ),
+            MapFileError::NegativeShips { index, ships } => {
+                write!(f, "World {} starts with a negative ship count ({})", index, ships)
+            }
+            MapFileError::InvalidOwner { index, owner } => {
+                write!(f, "World {} is owned by {:?}, who is not a player on this map", index, owner)
+            }
+            MapFileError::InvalidOrbit { index } => write!(
+                f,
+                "World {} needs an orbit with a period that circles a point or another world",
+                index
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MapFileError {}
+
+impl From<std::io::Error> for MapFileError {
+    fn from(e: std::io::Error) -> Self {
+        MapFileError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for MapFileError {
+    fn from(e: serde_json::Error) -> Self {
+        MapFileError::Json(e)
+    }
+}
+
+impl From<bincode::Error> for MapFileError {
+    fn from(e: bincode::Error) -> Self {
+        MapFileError::Bincode(e)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapMetadata {
+    pub name: String,
+    pub author: String,
+    pub player_count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapFileWorld {
+    pub pos: Vec2i,
+    pub size: PlanetSize,
+    pub owner: Team,
+    pub ships: i32,
+    #[serde(default)]
+    pub orbit: Option<Orbit>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapFile {
+    pub version: u32,
+    pub metadata: MapMetadata,
+    pub size: Vec2i,
+    pub worlds: Vec<MapFileWorld>,
+}
+
+#[derive(Deserialize)]
+struct MapFileHeader {
+    version: u32,
+}
+
+impl MapFile {
+    pub fn new(metadata: MapMetadata, size: Vec2i, worlds: Vec<MapFileWorld>) -> MapFile {
+        MapFile {
+            version: MAP_FILE_VERSION,
+            metadata,
+            size,
+            worlds,
+        }
+    }
+
+    pub fn from_worlds(metadata: MapMetadata, size: Vec2i, worlds: &[World]) -> MapFile {
+        let worlds = worlds
+            .iter()
+            .map(|world| MapFileWorld {
+                pos: world.pos,
+                size: world.size,
+                owner: world.team,
+                ships: world.ship_count,
+                orbit: world.orbit,
+            })
+            .collect();
+
+        MapFile::new(metadata, size, worlds)
+    }
+
+    pub fn to_worlds(&self) -> Vec<World> {
+        self.worlds
+            .iter()
+            .enumerate()
+            .map(|(index, world)| World {
+                id: WorldId(index as u32),
+                pos: world.pos,
+                ship_count: world.ships,
+                size: world.size,
+                team: world.owner,
+                orbit: world.orbit,
+            })
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<(), MapFileError> {
+        if self.version != MAP_FILE_VERSION {
+            return Err(MapFileError::UnsupportedVersion {
+                found: self.version,
+                expected: MAP_FILE_VERSION,
+            });
+        }
+
+        if self.size.x <= 0 || self.size.y <= 0 {
+            return Err(MapFileError::InvalidSize(self.size));
+        }
+
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&self.metadata.player_count) {
+            return Err(MapFileError::InvalidPlayerCount(self.metadata.player_count));
+        }
+
+        if self.worlds.is_empty() {
+            return Err(MapFileError::NoWorlds);
+        }
+
+        let half_size = self.size / 2;
+        for (index, world) in self.worlds.iter().enumerate() {
+            if world.pos.x.abs() > half_size.x || world.pos.y.abs() > half_size.y {
+                return Err(MapFileError::WorldOutOfBounds {
+                    index,
+                    pos: world.pos,
+                });
+            }
+
+            if world.ships < 0 {
+                return Err(MapFileError::NegativeShips {
+                    index,
+                    ships: world.ships,
+                });
+            }
+
+            if let Some(player) = world.owner.index() {
+                if player as u32 >= self.metadata.player_count {
+                    return Err(MapFileError::InvalidOwner {
+                        index,
+                        owner: world.owner,
+                    });
+                }
+            }
+
+            if matches!(world.orbit, Some(Orbit { period_ticks: 0, .. })) {
+                return Err(MapFileError::InvalidOrbit { index });
+            }
+        }
+
+        if let Some(index) = orbit::find_invalid_orbit(&self.to_worlds()) {
+            return Err(MapFileError::InvalidOrbit { index });
+        }
+
+        Ok(())
+    }
+
+    pub fn from_json_str(json: &str) -> Result<MapFile, MapFileError> {
+        let header: MapFileHeader = serde_json::from_str(json)?;
+        if header.version != MAP_FILE_VERSION {
+            return Err(MapFileError::UnsupportedVersion {
+                found: header.version,
+                expected: MAP_FILE_VERSION,
+            });
+        }
+
+        let map: MapFile = serde_json::from_str(json)?;
+        map.validate()?;
+        Ok(map)
+    }
+
+    pub fn to_json_string(&self) -> Result<String, MapFileError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<MapFile, MapFileError> {
+        if bytes.len() < 8 || &bytes[..4] != BINARY_MAGIC {
+            return Err(MapFileError::NotABinaryMap);
+        }
+
+        let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        if version != MAP_FILE_VERSION {
+            return Err(MapFileError::UnsupportedVersion {
+                found: version,
+                expected: MAP_FILE_VERSION,
+            });
+        }
+
+        // The same encoding bincode::serialize writes, only with a limit. Read through a reader,
+        // which checks every length against the limit before allocating for it.
+        let map: MapFile = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(MAX_BINARY_MAP_SIZE)
+            .deserialize_from(&bytes[8..])?;
+        map.validate()?;
+        Ok(map)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MapFileError> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(BINARY_MAGIC);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend(bincode::serialize(self)?);
+        Ok(bytes)
+    }
+
+    pub fn load_json(path: impl AsRef<Path>) -> Result<MapFile, MapFileError> {
+        let json = std::fs::read_to_string(path)?;
+        MapFile::from_json_str(&json)
+    }
+
+    pub fn save_json(&self, path: impl AsRef<Path>) -> Result<(), MapFileError> {
+        std::fs::write(path, self.to_json_string()?)?;
+        Ok(())
+    }
+
+    pub fn load_binary(path: impl AsRef<Path>) -> Result<MapFile, MapFileError> {
+        let bytes = std::fs::read(path)?;
+        MapFile::from_bytes(&bytes)
+    }
+
+    pub fn save_binary(&self, path: impl AsRef<Path>) -> Result<(), MapFileError> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Picks the format from the extension, `.json` is read as json and anything else as binary.
+    pub fn load(path: impl AsRef<Path>) -> Result<MapFile, MapFileError> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => MapFile::load_json(path),
+            _ => MapFile::load_binary(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orbit::OrbitCenter;
+
+    fn test_map() -> MapFile {
+        MapFile::new(
+            MapMetadata {
+                name: String::from("Twin Suns"),
+                author: String::from("Dec"),
+                player_count: 2,
+            },
+            Vec2i::new(500, 300),
+            vec![
+                MapFileWorld {
+                    pos: Vec2i::new(-200, 0),
+                    size: PlanetSize::Large,
+                    owner: Team::player(0),
+                    ships: 30,
+                    orbit: None,
+                },
+                MapFileWorld {
+                    pos: Vec2i::new(0, 0),
+                    size: PlanetSize::Small,
+                    owner: Team::Neutral,
+                    ships: 10,
+                    orbit: None,
+                },
+                MapFileWorld {
+                    pos: Vec2i::new(200, 0),
+                    size: PlanetSize::Large,
+                    owner: Team::player(1),
+                    ships: 30,
+                    orbit: None,
+                },
+                MapFileWorld {
+                    pos: Vec2i::new(0, 60),
+                    size: PlanetSize::Small,
+                    owner: Team::Neutral,
+                    ships: 5,
+                    orbit: Some(Orbit {
+                        center: OrbitCenter::World(WorldId(1)),
+                        offset: Vec2i::new(0, 60),
+                        period_ticks: 480,
+                    }),
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let map = test_map();
+        let json = map.to_json_string().unwrap();
+        assert_eq!(MapFile::from_json_str(&json).unwrap(), map);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let map = test_map();
+        let bytes = map.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], b"OMAP");
+        assert_eq!(MapFile::from_bytes(&bytes).unwrap(), map);
+    }
+
+    #[test]
+    fn documented_example_parses() {
+        let json = r#"{
+            "version": 3,
+            "metadata": { "name": "Twin Suns", "author": "Dec", "player_count": 2 },
+            "size": { "x": 500, "y": 300 },
+            "worlds": [
+                { "pos": { "x": -200, "y": 0 }, "size": "Large", "owner": { "Player": 0 }, "ships": 30 },
+                { "pos": { "x": 0, "y": 0 }, "size": "Small", "owner": "Neutral", "ships": 10 },
+                { "pos": { "x": 200, "y": 0 }, "size": "Large", "owner": { "Player": 1 }, "ships": 30 },
+                { "pos": { "x": 0, "y": 60 }, "size": "Small", "owner": "Neutral", "ships": 5,
+                  "orbit": { "center": { "World": 1 }, "offset": { "x": 0, "y": 60 }, "period_ticks": 480 } }
+            ]
+        }"#;
+
+        assert_eq!(MapFile::from_json_str(json).unwrap(), test_map());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut map = test_map();
+        map.version = MAP_FILE_VERSION + 1;
+
+        let json = serde_json::to_string(&map).unwrap();
+        assert!(matches!(
+            MapFile::from_json_str(&json),
+            Err(MapFileError::UnsupportedVersion { .. })
+        ));
+
+        let bytes = map.to_bytes().unwrap();
+        assert!(matches!(
+            MapFile::from_bytes(&bytes),
+            Err(MapFileError::UnsupportedVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn binary_maps_can_not_claim_huge_sizes() {
+        let mut bytes = test_map().to_bytes().unwrap();
+        // The name's length prefix follows the version.
+        bytes[12..20].copy_from_slice(&u64::MAX.to_le_bytes());
+        match MapFile::from_bytes(&bytes) {
+            Err(MapFileError::Bincode(e)) => assert!(matches!(*e, bincode::ErrorKind::SizeLimit)),
+            other => panic!("Expected a size limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(matches!(
+            MapFile::from_json_str("{ \"version\": 1, \"worlds\": ["),
+            Err(MapFileError::Json(_))
+        ));
+        assert!(matches!(
+            MapFile::from_bytes(b"not a map"),
+            Err(MapFileError::NotABinaryMap)
+        ));
+    }
+
+    #[test]
+    fn validation_errors() {
+        let mut map = test_map();
+        map.worlds[2].pos = Vec2i::new(251, 0);
+        assert!(matches!(
+            map.validate(),
+            Err(MapFileError::WorldOutOfBounds { index: 2, .. })
+        ));
+
+        let mut map = test_map();
+        map.worlds[0].ships = -1;
+        assert!(matches!(
+            map.validate(),
+            Err(MapFileError::NegativeShips { index: 0, ships: -1 })
+        ));
+
+        let mut map = test_map();
+        map.worlds.clear();
+        assert!(matches!(map.validate(), Err(MapFileError::NoWorlds)));
+
+        let mut map = test_map();
+        map.size = Vec2i::new(0, 300);
+        assert!(matches!(map.validate(), Err(MapFileError::InvalidSize(_))));
+
+        let mut map = test_map();
+        map.metadata.player_count = 1;
+        assert!(matches!(
+            map.validate(),
+            Err(MapFileError::InvalidPlayerCount(1))
+        ));
+
+        let mut map = test_map();
+        map.worlds[0].owner = Team::player(2);
+        assert!(matches!(
+            map.validate(),
+            Err(MapFileError::InvalidOwner { index: 0, .. })
+        ));
+
+        let mut map = test_map();
+        map.worlds[3].orbit.as_mut().unwrap().center = OrbitCenter::World(WorldId(3));
+        assert!(matches!(map.validate(), Err(MapFileError::InvalidOrbit { index: 3 })));
+
+        let mut map = test_map();
+        map.worlds[3].orbit.as_mut().unwrap().period_ticks = 0;
+        assert!(matches!(map.validate(), Err(MapFileError::InvalidOrbit { index: 3 })));
+    }
+}
@@ -0,0 +1,488 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fixed::FixedVec2;
+use crate::math::*;
+use crate::rng::SimRng;
+use crate::simulation::*;
+
+/*
+   generate_random scatters worlds anywhere, it is kept so old seeds still
+   produce the same maps. generate_symmetric builds even maps instead: one
+   player's share of the map is generated and then copied for every other
+   player, by mirroring or by rotating it around the center. Every player
+   starts on an equal home world and sees the same neutrals at the same
+   distances.
+
+   Positions are integers, so rotations that are not a multiple of a quarter
+   turn get rounded. Only mirrored maps and rotations by quarter turns come
+   out exactly the same for everyone. For the other player counts a spot is
+   only used if every player's distances, in ticks of travel, stay within
+   MAX_ROUNDING_ERROR of player 0's, so those maps are as even as the grid
+   allows but not exactly fair.
+*/
+
+/// Builds the worlds for a random map. The same size, seed and player count always give the
+/// same worlds. Every player starts with at least one world, the rest are randomly owned or neutral.
+pub fn generate_random(size: Vec2i, seed: u64, player_count: u32) -> Vec<World> {
+    let mut rng = SimRng::new(seed);
+    let player_count = player_count as i32;
+    let world_count = rng.gen_range(4..18).max(player_count + 2);
+
+    let mut worlds = Vec::with_capacity(world_count as usize);
+    let half_size = size / 2;
+
+    for index in 0..world_count {
+        let ship_count = rng.gen_range(10..31);
+        let owner = if index < player_count {
+            index
+        } else {
+            rng.gen_range(-1..player_count)
+        };
+        let team = if owner < 0 {
+            Team::Neutral
+        } else {
+            Team::player(owner as usize)
+        };
+        let pos = Vec2i::new(
+            rng.gen_range(-half_size.x..half_size.x),
+            rng.gen_range(-half_size.y..half_size.y),
+        );
+        worlds.push(World {
+            id: WorldId(index as u32),
+            pos,
+            size: PlanetSize::random(&mut rng),
+            ship_count,
+            team,
+            orbit: None,
+        });
+    }
+
+    worlds
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symmetry {
+    /// Mirrored left to right for two players, across both axes for four.
+    Mirror,
+    /// The same slice of the map for every player, rotated around the center.
+    Rotational,
+}
+
+#[derive(Debug)]
+pub enum MapGenError {
+    UnsupportedPlayerCount { symmetry: Symmetry, player_count: u32 },
+    /// The home worlds did not fit the map with these margins and spacing.
+    NoRoom,
+}
+
+impl fmt::Display for MapGenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapGenError::UnsupportedPlayerCount {
+                symmetry,
+                player_count,
+            } => write!(f, "{:?} maps can not be made for {} players", symmetry, player_count),
+            MapGenError::NoRoom => write!(f, "Not enough room on the map for every home world"),
+        }
+    }
+}
+
+impl std::error::Error for MapGenError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MapGenSettings {
+    pub symmetry: Symmetry,
+    pub player_count: u32,
+    /// Neutral worlds in each player's share of the map, ones that do not fit are left out.
+    pub neutrals_per_player: u32,
+    pub home_ships: i32,
+    pub home_size: PlanetSize,
+    /// Space between the edge of a world and the edge of the map.
+    pub border_margin: i32,
+    /// Space between the edges of any two worlds.
+    pub min_spacing: i32,
+    /// Adds a neutral in the middle, as far from every player.
+    pub center_world: bool,
+}
+
+impl MapGenSettings {
+    /// Mirrored for two and four players, rotational for everything else.
+    pub fn for_players(player_count: u32) -> MapGenSettings {
+        let symmetry = if player_count == 2 || player_count == 4 {
+            Symmetry::Mirror
+        } else {
+            Symmetry::Rotational
+        };
+
+        MapGenSettings {
+            symmetry,
+            player_count,
+            neutrals_per_player: if player_count <= 4 { 3 } else { 2 },
+            home_ships: 20,
+            home_size: PlanetSize::Medium,
+            border_margin: 10,
+            min_spacing: 12,
+            center_world: true,
+        }
+    }
+
+    fn validate(&self) -> Result<(), MapGenError> {
+        let supported = match self.symmetry {
+            Symmetry::Mirror => self.player_count == 2 || self.player_count == 4,
+            Symmetry::Rotational => (MIN_PLAYERS..=MAX_PLAYERS).contains(&self.player_count),
+        };
+
+        if supported {
+            Ok(())
+        } else {
+            Err(MapGenError::UnsupportedPlayerCount {
+                symmetry: self.symmetry,
+                player_count: self.player_count,
+            })
+        }
+    }
+
+    /// Where `pos` in player 0's share ends up in `player`'s share.
+    fn copy_for(&self, pos: Vec2i, player: u32) -> Vec2i {
+        match self.symmetry {
+            Symmetry::Mirror => match player {
+                0 => pos,
+                1 => Vec2i::new(-pos.x, pos.y),
+                2 => Vec2i::new(-pos.x, -pos.y),
+                _ => Vec2i::new(pos.x, -pos.y),
+            },
+            Symmetry::Rotational => FixedVec2::from_ivec(pos)
+                .rotate_turns(player as i64, self.player_count as i64)
+                .round(),
+        }
+    }
+
+    /// Whether every player's copy of the map is exactly the same, without any rounding.
+    pub fn is_exact(&self) -> bool {
+        match self.symmetry {
+            Symmetry::Mirror => true,
+            Symmetry::Rotational => self.player_count == 2 || self.player_count == 4,
+        }
+    }
+
+    /// Whether player `i` sees the same distances from `from[i]` to every spot in `to` as
+    /// player 0 does, give or take the rounding allowed for this layout.
+    fn is_even(&self, from: &[Vec2i], to: &[Vec2i]) -> bool {
+        let tolerance = if self.is_exact() { 0 } else { MAX_ROUNDING_ERROR };
+        let distances = |from: Vec2i| {
+            let mut distances: Vec<i32> = to.iter().map(|&to| magnitude_i32(to - from)).collect();
+            distances.sort();
+            distances
+        };
+
+        let first = distances(from[0]);
+        from[1..].iter().all(|&from| {
+            distances(from)
+                .iter()
+                .zip(&first)
+                .all(|(a, b)| (a - b).abs() <= tolerance)
+        })
+    }
+
+    fn copies(&self, pos: Vec2i) -> Vec<Vec2i> {
+        (0..self.player_count)
+            .map(|player| self.copy_for(pos, player))
+            .collect()
+    }
+}
+
+const MAX_PLACEMENT_ATTEMPTS: u32 = 200;
+
+/// How many ticks of travel rounded rotations may add or take away for one player.
+pub const MAX_ROUNDING_ERROR: i32 = 1;
+
+struct Placement<'a> {
+    settings: &'a MapGenSettings,
+    half_size: Vec2i,
+    placed: Vec<(Vec2i, PlanetSize)>,
+    /// Home worlds by player, empty until they are placed.
+    homes: Vec<Vec2i>,
+}
+
+impl Placement<'_> {
+    fn fits(&self, positions: &[Vec2i], size: PlanetSize) -> bool {
+        let radius = size.size();
+        let inside = positions.iter().all(|pos| {
+            pos.x.abs() + radius + self.settings.border_margin <= self.half_size.x
+                && pos.y.abs() + radius + self.settings.border_margin <= self.half_size.y
+        });
+
+        let apart = |a: Vec2i, b: Vec2i, b_size: PlanetSize| {
+            let min_distance = (radius + b_size.size() + self.settings.min_spacing) as i64;
+            let d = a - b;
+            (d.x as i64).pow(2) + (d.y as i64).pow(2) >= min_distance * min_distance
+        };
+
+        inside
+            && positions.iter().enumerate().all(|(index, &pos)| {
+                positions[index + 1..].iter().all(|&other| apart(pos, other, size))
+                    && self.placed.iter().all(|&(other, other_size)| apart(pos, other, other_size))
+            })
+    }
+
+    /// Tries random spots until all copies of one fit, `accept` can rule out spots up front.
+    fn place(
+        &mut self,
+        rng: &mut SimRng,
+        size: PlanetSize,
+        accept: impl Fn(Vec2i) -> bool,
+    ) -> Option<Vec<Vec2i>> {
+        for _ in 0..MAX_PLACEMENT_ATTEMPTS {
+            let pos = Vec2i::new(
+                rng.gen_range(-self.half_size.x..self.half_size.x),
+                rng.gen_range(-self.half_size.y..self.half_size.y),
+            );
+            if !accept(pos) {
+                continue;
+            }
+
+            let copies = self.settings.copies(pos);
+            // Homes have to be as far from the center as each other, neutrals as far from every home.
+            let even = if self.homes.is_empty() {
+                self.settings.is_even(&copies, &[Vec2i::new(0, 0)])
+            } else {
+                self.settings.is_even(&self.homes, &copies)
+            };
+            if even && self.fits(&copies, size) {
+                self.placed.extend(copies.iter().map(|&copy| (copy, size)));
+                return Some(copies);
+            }
+        }
+
+        None
+    }
+}
+
+/// Builds a symmetric map, the same size, seed and settings always give the same worlds. Worlds
+/// 0 to player_count - 1 are the home worlds of the players in order.
+pub fn generate_symmetric(size: Vec2i, seed: u64, settings: &MapGenSettings) -> Result<Vec<World>, MapGenError> {
+    settings.validate()?;
+
+    let mut rng = SimRng::new(seed);
+    let mut placement = Placement {
+        settings,
+        half_size: size / 2,
+        placed: Vec::new(),
+        homes: Vec::new(),
+    };
+    let mut worlds = Vec::new();
+    let add_world = |worlds: &mut Vec<World>, pos, size, ship_count, team| {
+        worlds.push(World {
+            id: WorldId(worlds.len() as u32),
+            pos,
+            ship_count,
+            size,
+            team,
+            orbit: None,
+        })
+    };
+
+    // Homes keep to the outer half of the map so players do not start next to each other.
+    let min_home_distance = placement.half_size.x.min(placement.half_size.y) / 2;
+    let homes = placement
+        .place(&mut rng, settings.home_size, |pos| magnitude_i32(pos) >= min_home_distance)
+        .ok_or(MapGenError::NoRoom)?;
+    for (player, &pos) in homes.iter().enumerate() {
+        add_world(&mut worlds, pos, settings.home_size, settings.home_ships, Team::player(player));
+    }
+    placement.homes = homes;
+
+    if settings.center_world {
+        let world_size = PlanetSize::random(&mut rng);
+        let ship_count = rng.gen_range(10..31);
+        let center = Vec2i::new(0, 0);
+        if placement.fits(&[center], world_size) {
+            placement.placed.push((center, world_size));
+            add_world(&mut worlds, center, world_size, ship_count, Team::Neutral);
+        }
+    }
+
+    for _ in 0..settings.neutrals_per_player {
+        let world_size = PlanetSize::random(&mut rng);
+        let ship_count = rng.gen_range(5..26);
+        if let Some(copies) = placement.place(&mut rng, world_size, |_| true) {
+            for pos in copies {
+                add_world(&mut worlds, pos, world_size, ship_count, Team::Neutral);
+            }
+        }
+    }
+
+    Ok(worlds)
+}
+
+/// Size of the maps random matches are played on, online clients and the server have to agree on it.
+pub const MATCH_MAP_SIZE: Vec2i = Vec2i::new(250 * 2, 150 * 2);
+
+/// Seeds tried for one match before giving up on it.
+const MATCH_SEED_ATTEMPTS: u64 = 8;
+
+/// The symmetric map of a seeded match. Seeds whose layout does not fit are followed by seeds
+/// derived from them, everyone who knows the seed and player count ends up with the same worlds.
+pub fn generate_for_match(size: Vec2i, seed: u64, player_count: u32) -> Result<Vec<World>, MapGenError> {
+    let settings = MapGenSettings::for_players(player_count);
+    let mut result = generate_symmetric(size, seed, &settings);
+    for attempt in 1..MATCH_SEED_ATTEMPTS {
+        if result.is_ok() {
+            break;
+        }
+        let derived = seed ^ attempt.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        result = generate_symmetric(size, derived, &settings);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_worlds() {
+        let size = Vec2i::new(500, 300);
+        let a = generate_random(size, 1234, 2);
+        let b = generate_random(size, 1234, 2);
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn different_seed_different_worlds() {
+        let size = Vec2i::new(500, 300);
+        let a = generate_random(size, 1, 2);
+        let b = generate_random(size, 2, 2);
+
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn seed_output_is_stable() {
+        // If this fails the generator changed and old seeds no longer reproduce old maps.
+        let worlds = generate_random(Vec2i::new(500, 300), 42, 2);
+
+        let summary: Vec<(i32, i32, i32, PlanetSize, Team)> = worlds
+            .iter()
+            .take(3)
+            .map(|w| (w.pos.x, w.pos.y, w.ship_count, w.size, w.team))
+            .collect();
+
+        assert_eq!(worlds.len(), 15);
+        assert_eq!(
+            summary,
+            vec![
+                (74, -95, 28, PlanetSize::Medium, Team::player(0)),
+                (35, -105, 26, PlanetSize::Small, Team::player(1)),
+                (243, 124, 24, PlanetSize::Medium, Team::Neutral),
+            ]
+        );
+    }
+
+    #[test]
+    fn every_player_gets_a_world() {
+        for player_count in MIN_PLAYERS..=MAX_PLAYERS {
+            let worlds = generate_random(Vec2i::new(500, 300), 7, player_count);
+            for index in 0..player_count as usize {
+                assert!(worlds.iter().any(|world| world.team == Team::player(index)));
+            }
+        }
+    }
+
+    fn neutral_distances(worlds: &[World], team: Team) -> Vec<i32> {
+        let home = worlds.iter().find(|world| world.team == team).unwrap();
+        let mut distances: Vec<i32> = worlds
+            .iter()
+            .filter(|world| world.team.is_neutral())
+            .map(|world| magnitude_i32(world.pos - home.pos))
+            .collect();
+        distances.sort();
+        distances
+    }
+
+    #[test]
+    fn symmetric_maps_are_even() {
+        let size = Vec2i::new(500, 300);
+        for player_count in MIN_PLAYERS..=MAX_PLAYERS {
+            for seed in 0..20 {
+                let settings = MapGenSettings::for_players(player_count);
+                let worlds = generate_symmetric(size, seed, &settings).unwrap();
+                assert_eq!(worlds, generate_symmetric(size, seed, &settings).unwrap());
+
+                let first = neutral_distances(&worlds, Team::player(0));
+                for player in 0..player_count as usize {
+                    let home = &worlds[player];
+                    assert_eq!(home.team, Team::player(player));
+                    assert_eq!(home.ship_count, settings.home_ships);
+
+                    let tolerance = if settings.is_exact() { 0 } else { MAX_ROUNDING_ERROR };
+                    let distances = neutral_distances(&worlds, Team::player(player));
+                    assert_eq!(distances.len(), first.len());
+                    for (a, b) in distances.iter().zip(&first) {
+                        assert!((a - b).abs() <= tolerance, "{} players, seed {}", player_count, seed);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn match_maps_are_symmetric_or_an_error() {
+        for player_count in MIN_PLAYERS..=MAX_PLAYERS {
+            let worlds = generate_for_match(MATCH_MAP_SIZE, 3, player_count).unwrap();
+            let settings = MapGenSettings::for_players(player_count);
+            assert_eq!(worlds, generate_symmetric(MATCH_MAP_SIZE, 3, &settings).unwrap());
+        }
+
+        assert!(matches!(
+            generate_for_match(Vec2i::new(40, 40), 3, 2),
+            Err(MapGenError::NoRoom)
+        ));
+        assert!(matches!(
+            generate_for_match(MATCH_MAP_SIZE, 3, MAX_PLAYERS + 1),
+            Err(MapGenError::UnsupportedPlayerCount { .. })
+        ));
+    }
+
+    #[test]
+    fn symmetric_maps_keep_spacing_and_margins() {
+        let size = Vec2i::new(500, 300);
+        for player_count in MIN_PLAYERS..=MAX_PLAYERS {
+            let settings = MapGenSettings::for_players(player_count);
+            let worlds = generate_symmetric(size, 5, &settings).unwrap();
+            assert!(worlds.len() > player_count as usize);
+
+            for (index, world) in worlds.iter().enumerate() {
+                let radius = world.size.size();
+                assert!(world.pos.x.abs() + radius + settings.border_margin <= size.x / 2);
+                assert!(world.pos.y.abs() + radius + settings.border_margin <= size.y / 2);
+
+                for other in &worlds[index + 1..] {
+                    let gap = radius + other.size.size() + settings.min_spacing;
+                    assert!(magnitude_i32(world.pos - other.pos) >= gap, "{:?} {:?}", world, other);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let mut settings = MapGenSettings::for_players(3);
+        settings.symmetry = Symmetry::Mirror;
+
+        assert!(matches!(
+            generate_symmetric(Vec2i::new(500, 300), 1, &settings),
+            Err(MapGenError::UnsupportedPlayerCount { player_count: 3, .. })
+        ));
+
+        let mut settings = MapGenSettings::for_players(2);
+        settings.border_margin = 200;
+        assert!(matches!(
+            generate_symmetric(Vec2i::new(500, 300), 1, &settings),
+            Err(MapGenError::NoRoom)
+        ));
+    }
+}
@@ -0,0 +1,58 @@
+use cgmath::Vector2;
+
+use crate::fixed::isqrt;
+
+pub type Vec2i = Vector2<i32>;
+
+/// Length rounded to the nearest integer, integer math only so it is safe to use accross the network.
+pub fn magnitude_i32(v: Vec2i) -> i32 {
+    let x = v.x as i64;
+    let y = v.y as i64;
+    let n = (x * x + y * y) as u64;
+    let r = isqrt(n);
+
+    // (r + 0.5)^2 = r^2 + r + 0.25, so anything above r^2 + r rounds up.
+    if n - r * r > r {
+        (r + 1) as i32
+    } else {
+        r as i32
+    }
+}
+
+/// 64 bit FNV-1a, used for state checksums so it must never change.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magnitude_rounds_to_nearest() {
+        assert_eq!(magnitude_i32(Vec2i::new(30, 40)), 50);
+        assert_eq!(magnitude_i32(Vec2i::new(1, 1)), 1);
+        assert_eq!(magnitude_i32(Vec2i::new(1, 2)), 2);
+        assert_eq!(magnitude_i32(Vec2i::new(-3, 3)), 4);
+        assert_eq!(magnitude_i32(Vec2i::new(0, 0)), 0);
+
+        for x in -200..200 {
+            for y in -200..200 {
+                let expected = ((x * x + y * y) as f64).sqrt().round() as i32;
+                assert_eq!(magnitude_i32(Vec2i::new(x, y)), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn fnv1a_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+}
@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+
+use crate::fixed::FixedVec2;
+use crate::math::*;
+use crate::simulation::*;
+
+/*
+   Worlds can circle a fixed point or another world. Where a world is only
+   depends on its orbit and the simulation time, so positions can be looked up
+   for any tick in the future, which is what squadrons use to aim: they fly a
+   straight line to the spot where their target will be when they get there.
+
+   A world orbiting another world follows it around, chains of orbits work as
+   long as they do not loop back on themselves.
+*/
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OrbitCenter {
+    Point(Vec2i),
+    World(WorldId),
+}
+
+/// On tick 0 the world is `offset` away from its center and it goes around once every
+/// `period_ticks`, counter clockwise, or clockwise for a negative period.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Orbit {
+    pub center: OrbitCenter,
+    pub offset: Vec2i,
+    pub period_ticks: i32,
+}
+
+/// Where `world` is at `time`, counted in ticks since the start of the match.
+pub fn position_at(worlds: &[World], world: &World, time: i32) -> Vec2i {
+    position_at_depth(worlds, world, time, 0)
+}
+
+fn position_at_depth(worlds: &[World], world: &World, time: i32, depth: usize) -> Vec2i {
+    let orbit = match &world.orbit {
+        Some(orbit) if orbit.period_ticks != 0 => orbit,
+        _ => return world.pos,
+    };
+
+    let center = match orbit.center {
+        OrbitCenter::Point(pos) => pos,
+        // A loop of orbits has no answer, stopping at the depth keeps it from recursing forever.
+        OrbitCenter::World(id) => match worlds.iter().find(|center| center.id == id) {
+            Some(center) if depth < worlds.len() => position_at_depth(worlds, center, time, depth + 1),
+            Some(center) => center.pos,
+            None => world.pos,
+        },
+    };
+
+    center
+        + FixedVec2::from_ivec(orbit.offset)
+            .rotate_turns(time as i64, orbit.period_ticks as i64)
+            .round()
+}
+
+/// Worlds without an orbit, or with a period of zero, stay where they are.
+pub fn is_moving(world: &World) -> bool {
+    matches!(&world.orbit, Some(orbit) if orbit.period_ticks != 0)
+}
+
+/// Index of the first world whose orbit is broken: it circles itself, a world that does not
+/// exist, or ends up back at itself through other orbits.
+pub fn find_invalid_orbit(worlds: &[World]) -> Option<usize> {
+    worlds.iter().position(|world| {
+        let mut current = world;
+        for _ in 0..=worlds.len() {
+            let next = match &current.orbit {
+                Some(Orbit {
+                    center: OrbitCenter::World(id),
+                    ..
+                }) => *id,
+                _ => return false,
+            };
+
+            current = match worlds.iter().find(|other| other.id == next) {
+                Some(other) if other.id != world.id => other,
+                _ => return true,
+            };
+        }
+        true
+    })
+}
+
+/// Where a squadron leaving `from` at `time` and flying a unit per tick meets `target`, and
+/// after how many ticks. Worlds that never get within reach are flown to where they are now.
+pub fn intercept(worlds: &[World], from: Vec2i, target: &World, time: i32, max_ticks: i32) -> (Vec2i, i32) {
+    let now = position_at(worlds, target, time);
+    let direct = magnitude_i32(now - from).max(1);
+    if !is_moving(target) {
+        return (now, direct);
+    }
+
+    for ticks in 1..=max_ticks {
+        let pos = position_at(worlds, target, time + ticks);
+        if magnitude_i32(pos - from) <= ticks {
+            return (pos, ticks);
+        }
+    }
+
+    (now, direct)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::world;
+
+    fn orbiting(id: u32, orbit: Option<Orbit>) -> World {
+        World {
+            orbit,
+            ..world(id, 0, 10, Team::Neutral)
+        }
+    }
+
+    fn orbit(center: OrbitCenter, offset: Vec2i, period_ticks: i32) -> Option<Orbit> {
+        Some(Orbit {
+            center,
+            offset,
+            period_ticks,
+        })
+    }
+
+    #[test]
+    fn worlds_go_around_their_center() {
+        let center = OrbitCenter::Point(Vec2i::new(10, 10));
+        let planet = orbiting(0, orbit(center, Vec2i::new(100, 0), 40));
+        let worlds = vec![planet.clone()];
+
+        assert_eq!(position_at(&worlds, &planet, 0), Vec2i::new(110, 10));
+        assert_eq!(position_at(&worlds, &planet, 10), Vec2i::new(10, 110));
+        assert_eq!(position_at(&worlds, &planet, 20), Vec2i::new(-90, 10));
+        assert_eq!(position_at(&worlds, &planet, 40), Vec2i::new(110, 10));
+
+        let backwards = orbiting(1, orbit(center, Vec2i::new(100, 0), -40));
+        assert_eq!(position_at(&worlds, &backwards, 10), Vec2i::new(10, -90));
+    }
+
+    #[test]
+    fn moons_follow_their_planet() {
+        let sun = OrbitCenter::Point(Vec2i::new(0, 0));
+        let planet = orbiting(0, orbit(sun, Vec2i::new(100, 0), 40));
+        let moon = orbiting(1, orbit(OrbitCenter::World(WorldId(0)), Vec2i::new(20, 0), 20));
+        let worlds = vec![planet, moon.clone()];
+
+        assert_eq!(position_at(&worlds, &moon, 0), Vec2i::new(120, 0));
+        assert_eq!(position_at(&worlds, &moon, 10), Vec2i::new(-20, 100));
+    }
+
+    #[test]
+    fn orbit_loops_are_found() {
+        let around = |id| orbit(OrbitCenter::World(WorldId(id)), Vec2i::new(10, 0), 10);
+        let fine = vec![
+            orbiting(0, None),
+            orbiting(1, around(0)),
+            orbiting(2, around(1)),
+        ];
+        assert_eq!(find_invalid_orbit(&fine), None);
+
+        let looped = vec![orbiting(0, around(1)), orbiting(1, around(0))];
+        assert_eq!(find_invalid_orbit(&looped), Some(0));
+        assert_eq!(find_invalid_orbit(&[orbiting(0, around(0))]), Some(0));
+        assert_eq!(find_invalid_orbit(&[orbiting(0, around(7))]), Some(0));
+
+        // Still answers instead of recursing forever.
+        position_at(&looped, &looped[0], 5);
+    }
+
+    #[test]
+    fn intercept_meets_the_world_where_it_will_be() {
+        let target = orbiting(
+            0,
+            orbit(OrbitCenter::Point(Vec2i::new(0, 0)), Vec2i::new(100, 0), 400),
+        );
+        let worlds = vec![target.clone()];
+        let from = Vec2i::new(0, -200);
+
+        let (aim, ticks) = intercept(&worlds, from, &target, 0, 1000);
+        assert_eq!(aim, position_at(&worlds, &target, ticks));
+        assert!(magnitude_i32(aim - from) <= ticks);
+        assert!(magnitude_i32(position_at(&worlds, &target, ticks - 1) - from) > ticks - 1);
+
+        // Static worlds are flown to straight away.
+        let rock = World {
+            pos: Vec2i::new(30, 40),
+            ..world(1, 0, 10, Team::Neutral)
+        };
+        assert_eq!(intercept(&worlds, Vec2i::new(0, 0), &rock, 0, 1000), (Vec2i::new(30, 40), 50));
+    }
+}
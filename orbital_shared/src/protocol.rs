@@ -0,0 +1,362 @@
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::GamePacket;
+
+/*
+   TCP is a stream, it does not keep the boundaries between writes, so every
+   packet goes out as a frame:
+
+       length: u32, little endian, size of the payload
+       version: u16, little endian, PROTOCOL_VERSION of the sender
+       payload: the bincode encoded GamePacket
+
+   Frames from a different protocol version or with a payload above
+   MAX_PACKET_SIZE are rejected before the payload is read, a peer can not
+   make us allocate more than that.
+
+   The one exception is REJECTION_VERSION: those frames carry a plain UTF-8
+   reason instead of a packet and every version reads them, so a peer on
+   another protocol version still learns why it was turned away.
+
+   read_packet and write_packet block until a whole frame went through. For
+   sockets in non-blocking mode PacketReader and PacketWriter keep partial
+   frames around between calls instead.
+*/
+
+/// Bump whenever GamePacket or anything inside it changes shape.
+pub const PROTOCOL_VERSION: u16 = 4;
+
+/// Largest payload we accept, big enough for a DesyncState with a full simulation in it.
+pub const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+/// Version of frames written by write_rejection, it must never be used for PROTOCOL_VERSION.
+pub const REJECTION_VERSION: u16 = u16::MAX;
+
+const HEADER_SIZE: usize = 6;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Io(io::Error),
+    Bincode(bincode::Error),
+    TooLarge(usize),
+    VersionMismatch { ours: u16, theirs: u16 },
+    /// The other side turned us away with a rejection frame, whatever version it runs.
+    Rejected(String),
+    /// The other side closed the connection.
+    Closed,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::Io(e) => write!(f, "Connection error: {}", e),
+            ProtocolError::Bincode(e) => write!(f, "Malformed packet: {}", e),
+            ProtocolError::TooLarge(size) => write!(
+                f,
+                "Packet of {} bytes is larger than the maximum of {}",
+                size, MAX_PACKET_SIZE
+            ),
+            ProtocolError::VersionMismatch { ours, theirs } => write!(
+                f,
+                "Protocol version {} does not match ours, {}",
+                theirs, ours
+            ),
+            ProtocolError::Rejected(reason) => write!(f, "{}", reason),
+            ProtocolError::Closed => write!(f, "Connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::UnexpectedEof => ProtocolError::Closed,
+            _ => ProtocolError::Io(e),
+        }
+    }
+}
+
+impl From<bincode::Error> for ProtocolError {
+    fn from(e: bincode::Error) -> Self {
+        ProtocolError::Bincode(e)
+    }
+}
+
+fn encode_frame(version: u16, payload: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+    if payload.len() > MAX_PACKET_SIZE {
+        return Err(ProtocolError::TooLarge(payload.len()));
+    }
+
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&version.to_le_bytes());
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+/// The whole frame for `packet`, header included.
+pub fn encode_packet(packet: &GamePacket) -> Result<Vec<u8>, ProtocolError> {
+    encode_frame(PROTOCOL_VERSION, &bincode::serialize(packet)?)
+}
+
+/// Payload size and version of the frame starting with `header`, once it checked out.
+fn parse_header(header: &[u8]) -> Result<(usize, u16), ProtocolError> {
+    let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let version = u16::from_le_bytes([header[4], header[5]]);
+
+    if version != PROTOCOL_VERSION && version != REJECTION_VERSION {
+        return Err(ProtocolError::VersionMismatch {
+            ours: PROTOCOL_VERSION,
+            theirs: version,
+        });
+    }
+    if length > MAX_PACKET_SIZE {
+        return Err(ProtocolError::TooLarge(length));
+    }
+    Ok((length, version))
+}
+
+fn decode_payload(version: u16, payload: &[u8]) -> Result<GamePacket, ProtocolError> {
+    if version == REJECTION_VERSION {
+        return Err(ProtocolError::Rejected(String::from_utf8_lossy(payload).into_owned()));
+    }
+    Ok(bincode::deserialize(payload)?)
+}
+
+pub fn write_packet<W: Write>(writer: &mut W, packet: &GamePacket) -> Result<(), ProtocolError> {
+    writer.write_all(&encode_packet(packet)?)?;
+    Ok(())
+}
+
+/// Turns the other side away with `reason`, readable no matter which protocol version it runs.
+pub fn write_rejection<W: Write>(writer: &mut W, reason: &str) -> Result<(), ProtocolError> {
+    writer.write_all(&encode_frame(REJECTION_VERSION, reason.as_bytes())?)?;
+    Ok(())
+}
+
+/// Blocks until a whole packet arrived.
+pub fn read_packet<R: Read>(reader: &mut R) -> Result<GamePacket, ProtocolError> {
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    let (length, version) = parse_header(&header)?;
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    decode_payload(version, &payload)
+}
+
+/// Collects bytes from a non-blocking stream until they make up whole packets.
+#[derive(Debug, Default)]
+pub struct PacketReader {
+    buffer: Vec<u8>,
+}
+
+impl PacketReader {
+    pub fn new() -> PacketReader {
+        PacketReader::default()
+    }
+
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// The oldest complete packet received so far, if there is one.
+    pub fn next_packet(&mut self) -> Result<Option<GamePacket>, ProtocolError> {
+        if self.buffer.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let (length, version) = parse_header(&self.buffer[..HEADER_SIZE])?;
+        if self.buffer.len() < HEADER_SIZE + length {
+            return Ok(None);
+        }
+
+        let packet = decode_payload(version, &self.buffer[HEADER_SIZE..HEADER_SIZE + length]);
+        self.buffer.drain(..HEADER_SIZE + length);
+        Ok(Some(packet?))
+    }
+
+    /// Reads whatever `reader` has right now and returns the next complete packet. Gives
+    /// `Ok(None)` instead of waiting when the packet is not all there yet.
+    pub fn poll<R: Read>(&mut self, reader: &mut R) -> Result<Option<GamePacket>, ProtocolError> {
+        if let Some(packet) = self.next_packet()? {
+            return Ok(Some(packet));
+        }
+
+        let mut chunk = [0; 4096];
+        loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => return Err(ProtocolError::Closed),
+                Ok(read) => {
+                    self.push_bytes(&chunk[..read]);
+                    if let Some(packet) = self.next_packet()? {
+                        return Ok(Some(packet));
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Queues frames for a non-blocking stream and sends as much as it takes on every flush.
+#[derive(Debug, Default)]
+pub struct PacketWriter {
+    pending: Vec<u8>,
+}
+
+impl PacketWriter {
+    pub fn new() -> PacketWriter {
+        PacketWriter::default()
+    }
+
+    pub fn queue(&mut self, packet: &GamePacket) -> Result<(), ProtocolError> {
+        self.pending.extend(encode_packet(packet)?);
+        Ok(())
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Writes queued bytes until they are all out or the stream would block. Returns whether
+    /// everything was sent.
+    pub fn flush<W: Write>(&mut self, writer: &mut W) -> Result<bool, ProtocolError> {
+        while !self.pending.is_empty() {
+            match writer.write(&self.pending) {
+                Ok(0) => return Err(ProtocolError::Closed),
+                Ok(written) => {
+                    self.pending.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        writer.flush()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::JoinPacket;
+
+    fn join(username: &str) -> GamePacket {
+        GamePacket::Join(JoinPacket::new(username))
+    }
+
+    fn username(packet: GamePacket) -> String {
+        match packet {
+            GamePacket::Join(join) => join.username,
+            other => panic!("Expected a join, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn packets_survive_a_round_trip() {
+        let mut bytes = Vec::new();
+        write_packet(&mut bytes, &join("first")).unwrap();
+        write_packet(&mut bytes, &join("second")).unwrap();
+
+        let mut reader = Cursor::new(bytes);
+        assert_eq!(username(read_packet(&mut reader).unwrap()), "first");
+        assert_eq!(username(read_packet(&mut reader).unwrap()), "second");
+        assert!(matches!(read_packet(&mut reader), Err(ProtocolError::Closed)));
+    }
+
+    #[test]
+    fn split_and_merged_frames_are_put_back_together() {
+        let mut bytes = encode_packet(&join("first")).unwrap();
+        bytes.extend(encode_packet(&join("second")).unwrap());
+
+        let mut reader = PacketReader::new();
+        let mut received = Vec::new();
+        for byte in bytes {
+            reader.push_bytes(&[byte]);
+            while let Some(packet) = reader.next_packet().unwrap() {
+                received.push(username(packet));
+            }
+        }
+        assert_eq!(received, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let mut frame = encode_packet(&join("player")).unwrap();
+        frame[4] = frame[4].wrapping_add(1);
+        assert!(matches!(
+            read_packet(&mut Cursor::new(frame)),
+            Err(ProtocolError::VersionMismatch { .. })
+        ));
+
+        let mut frame = encode_packet(&join("player")).unwrap();
+        frame[..4].copy_from_slice(&(MAX_PACKET_SIZE as u32 + 1).to_le_bytes());
+        let mut reader = PacketReader::new();
+        reader.push_bytes(&frame);
+        assert!(matches!(reader.next_packet(), Err(ProtocolError::TooLarge(_))));
+
+        assert!(matches!(
+            encode_packet(&join(&"x".repeat(MAX_PACKET_SIZE))),
+            Err(ProtocolError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn rejections_are_read_by_every_version() {
+        let mut frame = Vec::new();
+        write_rejection(&mut frame, "Too old").unwrap();
+        assert_eq!(u16::from_le_bytes([frame[4], frame[5]]), REJECTION_VERSION);
+
+        match read_packet(&mut Cursor::new(frame)) {
+            Err(ProtocolError::Rejected(reason)) => assert_eq!(reason, "Too old"),
+            other => panic!("Expected a rejection, got {:?}", other),
+        }
+    }
+
+    /// Hands out at most a few bytes per call and then claims it would block.
+    struct Trickle {
+        bytes: Vec<u8>,
+        blocked: bool,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.blocked = !self.blocked;
+            if self.blocked || self.bytes.is_empty() {
+                return Err(io::Error::new(ErrorKind::WouldBlock, "no data yet"));
+            }
+            let count = buf.len().min(self.bytes.len()).min(3);
+            buf[..count].copy_from_slice(&self.bytes[..count]);
+            self.bytes.drain(..count);
+            Ok(count)
+        }
+    }
+
+    #[test]
+    fn polling_never_waits_for_a_whole_packet() {
+        let mut stream = Trickle {
+            bytes: encode_packet(&join("player")).unwrap(),
+            blocked: false,
+        };
+
+        let mut reader = PacketReader::new();
+        let mut polls = 0;
+        let packet = loop {
+            polls += 1;
+            if let Some(packet) = reader.poll(&mut stream).unwrap() {
+                break packet;
+            }
+        };
+        assert_eq!(username(packet), "player");
+        assert!(polls > 1);
+    }
+}
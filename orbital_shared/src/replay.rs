@@ -0,0 +1,283 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::map_file::{MapFile, MapFileError};
+use crate::rules::{MatchRules, RulesError};
+use crate::simulation::*;
+
+/*
+   A match is fully described by its starting map, its players, its rules and
+   the tick stream, so a replay is just those. Playing it back means feeding the ticks into a
+   fresh Simulation, seeking backwards means doing that again from the start.
+
+   On disk a replay is the bytes OREP, the version as a little endian u32 and
+   then the bincode encoded Replay.
+*/
+
+pub const REPLAY_VERSION: u32 = 5;
+
+const REPLAY_MAGIC: &[u8; 4] = b"OREP";
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Bincode(bincode::Error),
+    NotAReplay,
+    UnsupportedVersion { found: u32, expected: u32 },
+    Map(MapFileError),
+    Rules(RulesError),
+    /// The replay has a different number of players than its map is made for.
+    PlayerCountMismatch { players: usize, map: u32 },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "Failed to read or write replay: {}", e),
+            ReplayError::Bincode(e) => write!(f, "Malformed replay: {}", e),
+            ReplayError::NotAReplay => write!(f, "File is not a replay, missing OREP header"),
+            ReplayError::UnsupportedVersion { found, expected } => write!(
+                f,
+                "Replay version {} is not supported, expected version {}",
+                found, expected
+            ),
+            ReplayError::Map(e) => write!(f, "Replay contains an invalid map: {}", e),
+            ReplayError::Rules(e) => write!(f, "Replay contains invalid rules: {}", e),
+            ReplayError::PlayerCountMismatch { players, map } => write!(
+                f,
+                "Replay has {} players but its map is made for {}",
+                players, map
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for ReplayError {
+    fn from(e: bincode::Error) -> Self {
+        ReplayError::Bincode(e)
+    }
+}
+
+impl From<MapFileError> for ReplayError {
+    fn from(e: MapFileError) -> Self {
+        ReplayError::Map(e)
+    }
+}
+
+impl From<RulesError> for ReplayError {
+    fn from(e: RulesError) -> Self {
+        ReplayError::Rules(e)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Replay {
+    pub version: u32,
+    pub map_seed: Option<u64>,
+    pub map: MapFile,
+    pub players: PlayerSetup,
+    pub rules: MatchRules,
+    pub ticks: Vec<Tick>,
+}
+
+impl Replay {
+    pub fn new(
+        map: MapFile,
+        map_seed: Option<u64>,
+        players: PlayerSetup,
+        rules: MatchRules,
+    ) -> Replay {
+        Replay {
+            version: REPLAY_VERSION,
+            map_seed,
+            map,
+            players,
+            rules,
+            ticks: Vec::new(),
+        }
+    }
+
+    pub fn record_tick(&mut self, tick: &Tick) {
+        self.ticks.push(tick.clone());
+    }
+
+    pub fn tick_count(&self) -> usize {
+        self.ticks.len()
+    }
+
+    pub fn initial_simulation(&self) -> Simulation {
+        Simulation::with_rules(
+            self.map.size,
+            self.map.to_worlds(),
+            self.players.clone(),
+            self.rules.clone(),
+        )
+    }
+
+    /// State after the first `tick_count` ticks, resimulated from the start.
+    pub fn simulation_at(&self, tick_count: usize) -> Simulation {
+        let mut sim = self.initial_simulation();
+        for tick in self.ticks.iter().take(tick_count) {
+            sim.tick(tick);
+        }
+        sim
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Replay, ReplayError> {
+        if bytes.len() < 8 || &bytes[..4] != REPLAY_MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+
+        let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion {
+                found: version,
+                expected: REPLAY_VERSION,
+            });
+        }
+
+        // Replays are handed to us as files, nothing in them may be able to make playback panic.
+        let replay: Replay = bincode::deserialize(&bytes[8..])?;
+        replay.map.validate()?;
+        replay.rules.validate()?;
+        if replay.players.player_count() != replay.map.metadata.player_count as usize {
+            return Err(ReplayError::PlayerCountMismatch {
+                players: replay.players.player_count(),
+                map: replay.map.metadata.player_count,
+            });
+        }
+        Ok(replay)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ReplayError> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(REPLAY_MAGIC);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend(bincode::serialize(self)?);
+        Ok(bytes)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Replay, ReplayError> {
+        let bytes = std::fs::read(path)?;
+        Replay::from_bytes(&bytes)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_file::MapMetadata;
+    use crate::map_gen;
+    use crate::math::Vec2i;
+
+    fn recorded_match() -> (Replay, Simulation) {
+        let size = Vec2i::new(500, 300);
+        let worlds = map_gen::generate_random(size, 99, 2);
+        let map = MapFile::from_worlds(
+            MapMetadata {
+                name: String::from("Random 99"),
+                author: String::from("test"),
+                player_count: 2,
+            },
+            size,
+            &worlds,
+        );
+
+        let players = PlayerSetup::free_for_all(2).unwrap();
+        let mut replay = Replay::new(map, Some(99), players.clone(), MatchRules::default());
+        let mut sim = Simulation::new(size, worlds, players);
+
+        for tick_number in 0..500 {
+            let mut tick = Tick::new(tick_number, 2);
+            if tick_number == 10 {
+                tick.push_player_action(
+                    Team::player(0),
+                    PlayerAction::Attack(PlayerActionAttack {
+                        sources: vec![WorldId(0)],
+                        target: WorldId(1),
+                        ratio: SendRatio::All,
+                    }),
+                );
+            }
+
+            sim.tick(&tick);
+            replay.record_tick(&tick);
+        }
+
+        (replay, sim)
+    }
+
+    #[test]
+    fn playback_reproduces_the_match() {
+        let (replay, sim) = recorded_match();
+        assert_eq!(replay.simulation_at(replay.tick_count()).checksum(), sim.checksum());
+    }
+
+    #[test]
+    fn seeking_resimulates() {
+        let (replay, _) = recorded_match();
+
+        let mut sim = replay.initial_simulation();
+        for tick in &replay.ticks[..123] {
+            sim.tick(tick);
+        }
+
+        assert_eq!(replay.simulation_at(123).checksum(), sim.checksum());
+        assert_eq!(
+            replay.simulation_at(0).checksum(),
+            replay.initial_simulation().checksum()
+        );
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let (replay, sim) = recorded_match();
+
+        let loaded = Replay::from_bytes(&replay.to_bytes().unwrap()).unwrap();
+        assert_eq!(loaded.map_seed, Some(99));
+        assert_eq!(loaded.tick_count(), 500);
+        assert_eq!(loaded.simulation_at(500).checksum(), sim.checksum());
+
+        assert!(matches!(
+            Replay::from_bytes(b"OMAP\x01\x00\x00\x00"),
+            Err(ReplayError::NotAReplay)
+        ));
+    }
+
+    #[test]
+    fn rejects_replays_that_could_not_be_played() {
+        let (replay, _) = recorded_match();
+
+        let mut broken_rules = replay.clone();
+        broken_rules.rules.production.interval_ticks = 0;
+        assert!(matches!(
+            Replay::from_bytes(&broken_rules.to_bytes().unwrap()),
+            Err(ReplayError::Rules(_))
+        ));
+
+        let mut extra_player = replay;
+        extra_player.players = PlayerSetup::free_for_all(3).unwrap();
+        assert!(matches!(
+            Replay::from_bytes(&extra_player.to_bytes().unwrap()),
+            Err(ReplayError::PlayerCountMismatch { players: 3, map: 2 })
+        ));
+    }
+}
@@ -0,0 +1,92 @@
+use std::ops::Range;
+
+/*
+   PCG32 (XSH-RR), see https://www.pcg-random.org. We carry our own instead of
+   using the rand crate because every client has to produce the exact same
+   numbers from the same seed, on every platform and forever, and rand makes
+   no promises about value stability between versions.
+*/
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+const PCG_DEFAULT_STREAM: u64 = 54;
+
+#[derive(Debug, Clone)]
+pub struct SimRng {
+    state: u64,
+    inc: u64,
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> SimRng {
+        SimRng::new_with_stream(seed, PCG_DEFAULT_STREAM)
+    }
+
+    pub fn new_with_stream(seed: u64, stream: u64) -> SimRng {
+        let mut rng = SimRng {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state
+            .wrapping_mul(PCG_MULTIPLIER)
+            .wrapping_add(self.inc);
+
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rot = (old_state >> 59) as u32;
+        xor_shifted.rotate_right(rot)
+    }
+
+    /// Uniform in [range.start, range.end), rejection sampled so there is no modulo bias.
+    pub fn gen_range(&mut self, range: Range<i32>) -> i32 {
+        assert!(range.start < range.end, "Empty range");
+
+        let span = (range.end as i64 - range.start as i64) as u64;
+        let threshold = (1u64 << 32) % span;
+        loop {
+            let r = self.next_u32() as u64;
+            if r >= threshold {
+                return (range.start as i64 + (r % span) as i64) as i32;
+            }
+        }
+    }
+
+    pub fn gen_bool(&mut self) -> bool {
+        self.next_u32() & 1 == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_pcg32_reference_output() {
+        // First outputs of the reference pcg32-demo with initstate 42 and initseq 54.
+        let mut rng = SimRng::new_with_stream(42, 54);
+        let expected = [
+            0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e,
+        ];
+
+        for value in expected {
+            assert_eq!(rng.next_u32(), value);
+        }
+    }
+
+    #[test]
+    fn gen_range_stays_in_bounds() {
+        let mut rng = SimRng::new(7);
+        for _ in 0..10_000 {
+            let v = rng.gen_range(-3..4);
+            assert!((-3..4).contains(&v));
+        }
+
+        assert_eq!(rng.gen_range(i32::MIN..i32::MIN + 1), i32::MIN);
+    }
+}
@@ -0,0 +1,270 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::PlanetSize;
+
+/*
+   Everything a match can be tuned with lives here. The rules are part of the
+   simulation state, so both peers and replays must agree on them.
+
+   Designers can keep rules in a json file and load it with MatchRules::load.
+   Every field has a default, so a file only needs the values it changes:
+
+   {
+       "production": {
+           "large": { "ships_per_interval": 4, "capacity": 120 },
+           "decay": null
+       }
+   }
+*/
+
+#[derive(Debug)]
+pub enum RulesError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RulesError::Io(e) => write!(f, "Failed to read rules file: {}", e),
+            RulesError::Json(e) => write!(f, "Malformed rules json: {}", e),
+            RulesError::Invalid(reason) => write!(f, "Invalid rules: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RulesError {}
+
+impl From<std::io::Error> for RulesError {
+    fn from(e: std::io::Error) -> Self {
+        RulesError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for RulesError {
+    fn from(e: serde_json::Error) -> Self {
+        RulesError::Json(e)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct VictoryRules {
+    /// A team that has no worlds and no squadrons left loses.
+    pub elimination: bool,
+    /// When set the match ends after this many ticks and the team with the highest score wins.
+    pub time_limit_ticks: Option<i32>,
+    pub allow_surrender: bool,
+}
+
+impl Default for VictoryRules {
+    fn default() -> Self {
+        VictoryRules {
+            elimination: true,
+            time_limit_ticks: None,
+            allow_surrender: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorldProduction {
+    pub ships_per_interval: i32,
+    /// Production stops once the garrison reaches this many ships.
+    pub capacity: i32,
+}
+
+/// Garrisons above capacity, from reinforcements or captures, shrink back towards it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecayRules {
+    pub interval_ticks: i32,
+    pub ships_per_interval: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ProductionRules {
+    pub interval_ticks: i32,
+    pub small: WorldProduction,
+    pub medium: WorldProduction,
+    pub large: WorldProduction,
+    pub decay: Option<DecayRules>,
+}
+
+impl ProductionRules {
+    pub fn for_size(&self, size: PlanetSize) -> &WorldProduction {
+        match size {
+            PlanetSize::Small => &self.small,
+            PlanetSize::Medium => &self.medium,
+            PlanetSize::Large => &self.large,
+        }
+    }
+}
+
+impl Default for ProductionRules {
+    fn default() -> Self {
+        ProductionRules {
+            interval_ticks: 30,
+            small: WorldProduction {
+                ships_per_interval: 1,
+                capacity: 30,
+            },
+            medium: WorldProduction {
+                ships_per_interval: 2,
+                capacity: 60,
+            },
+            large: WorldProduction {
+                ships_per_interval: 3,
+                capacity: 100,
+            },
+            decay: Some(DecayRules {
+                interval_ticks: 30,
+                ships_per_interval: 1,
+            }),
+        }
+    }
+}
+
+/// Enemy squadrons that pass within `range` of each other during a tick fight, both sides lose
+/// as many ships as the smaller one had.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct InterceptionRules {
+    pub range: i32,
+}
+
+impl Default for InterceptionRules {
+    fn default() -> Self {
+        InterceptionRules { range: 5 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct MatchRules {
+    pub victory: VictoryRules,
+    pub production: ProductionRules,
+    /// Squadrons fly through each other unless this is set.
+    pub interception: Option<InterceptionRules>,
+}
+
+impl MatchRules {
+    pub fn validate(&self) -> Result<(), RulesError> {
+        let production = &self.production;
+        if production.interval_ticks <= 0 {
+            return Err(RulesError::Invalid(String::from(
+                "production interval must be at least one tick",
+            )));
+        }
+
+        for size in [PlanetSize::Small, PlanetSize::Medium, PlanetSize::Large] {
+            let world = production.for_size(size);
+            if world.ships_per_interval < 0 || world.capacity < 0 {
+                return Err(RulesError::Invalid(format!(
+                    "{:?} worlds need a positive production and capacity",
+                    size
+                )));
+            }
+        }
+
+        if let Some(decay) = &production.decay {
+            if decay.interval_ticks <= 0 || decay.ships_per_interval < 0 {
+                return Err(RulesError::Invalid(String::from(
+                    "decay needs an interval of at least one tick and a positive ship count",
+                )));
+            }
+        }
+
+        if let Some(interception) = &self.interception {
+            if interception.range <= 0 {
+                return Err(RulesError::Invalid(String::from(
+                    "interception range must be at least one unit",
+                )));
+            }
+        }
+
+        if let Some(limit) = self.victory.time_limit_ticks {
+            if limit <= 0 {
+                return Err(RulesError::Invalid(String::from(
+                    "time limit must be at least one tick",
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn from_json_str(json: &str) -> Result<MatchRules, RulesError> {
+        let rules: MatchRules = serde_json::from_str(json)?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    pub fn to_json_string(&self) -> Result<String, RulesError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<MatchRules, RulesError> {
+        let json = std::fs::read_to_string(path)?;
+        MatchRules::from_json_str(&json)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RulesError> {
+        std::fs::write(path, self.to_json_string()?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_files_keep_defaults() {
+        let rules = MatchRules::from_json_str(
+            r#"{
+                "production": {
+                    "large": { "ships_per_interval": 4, "capacity": 120 },
+                    "decay": null
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(rules.production.large.ships_per_interval, 4);
+        assert_eq!(rules.production.decay, None);
+        assert_eq!(rules.production.small, ProductionRules::default().small);
+        assert_eq!(rules.victory, VictoryRules::default());
+        assert_eq!(rules.interception, None);
+
+        let rules = MatchRules::from_json_str(r#"{ "interception": {} }"#).unwrap();
+        assert_eq!(rules.interception, Some(InterceptionRules::default()));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let rules = MatchRules::default();
+        let json = rules.to_json_string().unwrap();
+        assert_eq!(MatchRules::from_json_str(&json).unwrap(), rules);
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert!(matches!(
+            MatchRules::from_json_str(r#"{ "production": { "interval_ticks": 0 } }"#),
+            Err(RulesError::Invalid(_))
+        ));
+        assert!(matches!(
+            MatchRules::from_json_str(r#"{ "production": { "medium": { "ships_per_interval": 1 } } }"#),
+            Err(RulesError::Json(_))
+        ));
+        assert!(matches!(
+            MatchRules::from_json_str(r#"{ "interception": { "range": 0 } }"#),
+            Err(RulesError::Invalid(_))
+        ));
+    }
+}
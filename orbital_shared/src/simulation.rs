@@ -0,0 +1,1564 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::combat::{paths_meet, resolve_arrivals, Arrival, CombatOutcome};
+use crate::events::SimEvent;
+use crate::fixed::{Fixed, FixedVec2};
+use crate::math::*;
+use crate::orbit::{self, Orbit};
+use crate::rng::SimRng;
+use crate::rules::{InterceptionRules, MatchRules};
+
+/*
+   The simulation is the deterministic part of a match. It knows nothing about
+   rendering, audio or input, it only advances on a Tick. Everything that
+   runs a match (client, server, bots, tests) goes through here.
+*/
+
+pub const MIN_PLAYERS: u32 = 2;
+pub const MAX_PLAYERS: u32 = 8;
+
+/// Owner of a world or squadron. Every player has their own, in team games allied players
+/// still keep separate ones and are grouped by [`PlayerSetup`].
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub enum Team {
+    Neutral,
+    Player(u8),
+}
+
+impl Team {
+    pub fn player(index: usize) -> Team {
+        Team::Player(index as u8)
+    }
+
+    /// Slot of the player in `Tick::player_actions`, None for neutral.
+    pub fn index(&self) -> Option<usize> {
+        match self {
+            Team::Neutral => None,
+            Team::Player(index) => Some(*index as usize),
+        }
+    }
+
+    pub fn is_neutral(&self) -> bool {
+        *self == Team::Neutral
+    }
+}
+
+/// The players of a match and which alliance each of them is in. Allies reinforce each
+/// other's worlds instead of attacking them and win or lose together.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct PlayerSetup {
+    alliances: Vec<u8>,
+}
+
+impl PlayerSetup {
+    /// `alliances[i]` is the alliance of `Team::player(i)`, there have to be at least two alliances.
+    pub fn new(alliances: Vec<u8>) -> Result<PlayerSetup, SimulationError> {
+        let player_count = alliances.len() as u32;
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&player_count) {
+            return Err(SimulationError::InvalidPlayerCount(player_count));
+        }
+
+        if alliances.iter().all(|alliance| *alliance == alliances[0]) {
+            return Err(SimulationError::SingleAlliance);
+        }
+
+        Ok(PlayerSetup { alliances })
+    }
+
+    pub fn free_for_all(player_count: u32) -> Result<PlayerSetup, SimulationError> {
+        PlayerSetup::new((0..player_count).map(|index| index as u8).collect())
+    }
+
+    /// Deals the players round robin into `team_count` teams, player 0 and `team_count` play together.
+    pub fn teams(player_count: u32, team_count: u32) -> Result<PlayerSetup, SimulationError> {
+        if team_count == 0 {
+            return Err(SimulationError::SingleAlliance);
+        }
+        PlayerSetup::new(
+            (0..player_count)
+                .map(|index| (index % team_count) as u8)
+                .collect(),
+        )
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.alliances.len()
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = Team> {
+        (0..self.alliances.len()).map(Team::player)
+    }
+
+    pub fn contains(&self, team: Team) -> bool {
+        self.alliance(team).is_some()
+    }
+
+    pub fn alliance(&self, team: Team) -> Option<u8> {
+        team.index()
+            .and_then(|index| self.alliances.get(index))
+            .copied()
+    }
+
+    /// Neutral is nobody's ally.
+    pub fn are_allied(&self, a: Team, b: Team) -> bool {
+        match (self.alliance(a), self.alliance(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    pub fn allies_of(&self, team: Team) -> Vec<Team> {
+        self.players()
+            .filter(|other| self.are_allied(team, *other))
+            .collect()
+    }
+}
+
+/// Handed out when the map is created and never reused, so it stays valid no matter where the world sits in the list.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct WorldId(pub u32);
+
+impl fmt::Display for WorldId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SimulationError {
+    UnknownWorld(WorldId),
+    UnknownSquadron(u32),
+    UnknownPlayer(Team),
+    NotOwner { world: WorldId, team: Team },
+    NotSquadronOwner { squadron: u32, team: Team },
+    /// An attack has to be sent from at least one world.
+    NoAttackSources,
+    AttackFromTarget(WorldId),
+    InvalidPlayerCount(u32),
+    SingleAlliance,
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulationError::UnknownWorld(id) => write!(f, "No world with id {}", id),
+            SimulationError::UnknownSquadron(id) => write!(f, "No squadron with id {}", id),
+            SimulationError::UnknownPlayer(team) => write!(f, "{:?} is not playing", team),
+            SimulationError::NotOwner { world, team } => {
+                write!(f, "World {} does not belong to {:?}", world, team)
+            }
+            SimulationError::NotSquadronOwner { squadron, team } => {
+                write!(f, "Squadron {} does not belong to {:?}", squadron, team)
+            }
+            SimulationError::NoAttackSources => write!(f, "The attack is not sent from any world"),
+            SimulationError::AttackFromTarget(id) => write!(f, "World {} can not attack itself", id),
+            SimulationError::InvalidPlayerCount(count) => write!(
+                f,
+                "A match needs between {} and {} players, not {}",
+                MIN_PLAYERS, MAX_PLAYERS, count
+            ),
+            SimulationError::SingleAlliance => {
+                write!(f, "All players are in the same alliance, nobody to play against")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+/// How much of each source world's fleet an attack takes with it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SendRatio {
+    Quarter,
+    Half,
+    ThreeQuarters,
+    All,
+}
+
+impl SendRatio {
+    pub const ALL_RATIOS: [SendRatio; 4] = [
+        SendRatio::Quarter,
+        SendRatio::Half,
+        SendRatio::ThreeQuarters,
+        SendRatio::All,
+    ];
+
+    pub fn percent(&self) -> i32 {
+        match self {
+            SendRatio::Quarter => 25,
+            SendRatio::Half => 50,
+            SendRatio::ThreeQuarters => 75,
+            SendRatio::All => 100,
+        }
+    }
+
+    /// Ships that leave a world holding `ship_count`. Rounds down, but a world
+    /// with ships always sends at least one.
+    pub fn ships_to_send(&self, ship_count: i32) -> i32 {
+        if ship_count <= 0 {
+            return 0;
+        }
+        (ship_count * self.percent() / 100).max(1)
+    }
+
+    pub fn higher(&self) -> SendRatio {
+        match self {
+            SendRatio::Quarter => SendRatio::Half,
+            SendRatio::Half => SendRatio::ThreeQuarters,
+            SendRatio::ThreeQuarters | SendRatio::All => SendRatio::All,
+        }
+    }
+
+    pub fn lower(&self) -> SendRatio {
+        match self {
+            SendRatio::Quarter | SendRatio::Half => SendRatio::Quarter,
+            SendRatio::ThreeQuarters => SendRatio::Half,
+            SendRatio::All => SendRatio::ThreeQuarters,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerActionAttack {
+    pub sources: Vec<WorldId>,
+    pub target: WorldId,
+    pub ratio: SendRatio,
+}
+
+/// Turns squadrons that are already flying towards a new target.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerActionRedirect {
+    pub squadrons: Vec<u32>,
+    pub target: WorldId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum PlayerAction {
+    None,
+    Attack(PlayerActionAttack),
+    Surrender,
+    Redirect(PlayerActionRedirect),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MatchEndReason {
+    Elimination,
+    TimeLimit,
+    Surrender,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct MatchResult {
+    /// Every player of the winning alliance, empty for a draw.
+    pub winners: Vec<Team>,
+    pub reason: MatchEndReason,
+    pub tick_number: i32,
+}
+
+/// Most actions one player gets into a single tick, the rest has to wait for the next one.
+pub const MAX_ACTIONS_PER_TICK: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tick {
+    pub tick_number: i32,
+    /// Indexed by player, each player's actions run in order. A missing entry means no actions.
+    pub player_actions: Vec<Vec<PlayerAction>>,
+}
+
+impl Tick {
+    pub fn new(tick_number: i32, player_count: usize) -> Tick {
+        Tick {
+            tick_number,
+            player_actions: vec![Vec::new(); player_count],
+        }
+    }
+
+    /// At most MAX_ACTIONS_PER_TICK, anything beyond that in a received tick is ignored.
+    pub fn player_actions(&self, team: Team) -> &[PlayerAction] {
+        let actions = team
+            .index()
+            .and_then(|index| self.player_actions.get(index))
+            .map(|actions| actions.as_slice())
+            .unwrap_or(&[]);
+        &actions[..actions.len().min(MAX_ACTIONS_PER_TICK)]
+    }
+
+    /// Queues `action` after the player's earlier ones. Returns false, and drops the action, when
+    /// the player already has MAX_ACTIONS_PER_TICK. `PlayerAction::None` is never stored.
+    pub fn push_player_action(&mut self, team: Team, action: PlayerAction) -> bool {
+        let index = match team.index() {
+            Some(index) => index,
+            None => return false,
+        };
+        if matches!(action, PlayerAction::None) {
+            return true;
+        }
+
+        if self.player_actions.len() <= index {
+            self.player_actions.resize(index + 1, Vec::new());
+        }
+        let actions = &mut self.player_actions[index];
+        if actions.len() >= MAX_ACTIONS_PER_TICK {
+            return false;
+        }
+        actions.push(action);
+        true
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Squadron {
+    pub id: u32,
+    pub start_pos: FixedVec2,
+    pub pos: FixedVec2,
+    pub ship_count: i32,
+    pub team: Team,
+    pub source_world: WorldId,
+    pub dest_world: WorldId,
+    /// Where the destination will be on arrival, squadrons fly a straight line to it.
+    pub target_pos: FixedVec2,
+    pub distance_in_ticks: i32,
+    pub travel_in_ticks: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PlanetSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl PlanetSize {
+    pub fn size(&self) -> i32 {
+        match self {
+            PlanetSize::Small => 8,
+            PlanetSize::Medium => 12,
+            PlanetSize::Large => 19,
+        }
+    }
+
+    pub fn random(rng: &mut SimRng) -> PlanetSize {
+        match rng.gen_range(0..3) {
+            0 => PlanetSize::Small,
+            1 => PlanetSize::Medium,
+            2 => PlanetSize::Large,
+            _ => panic!("Invalid random number"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct World {
+    pub id: WorldId,
+    pub pos: Vec2i,
+    pub ship_count: i32,
+    pub size: PlanetSize,
+    pub team: Team,
+    /// Worlds on an orbit have their `pos` moved every tick.
+    pub orbit: Option<Orbit>,
+}
+
+/// Longest a squadron waits for an orbiting world to come within reach before flying straight at it.
+const MAX_INTERCEPT_TICKS: i32 = 24 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Simulation {
+    size: Vec2i,
+    /// Ticks simulated so far, orbits are positioned by it.
+    time: i32,
+    worlds: Vec<World>,
+    squadrons: Vec<Squadron>,
+    next_squadron_id: u32,
+    players: PlayerSetup,
+    surrendered: Vec<Team>,
+    rules: MatchRules,
+    result: Option<MatchResult>,
+    /// What happened during the last tick, not part of the state and never checksummed.
+    #[serde(skip)]
+    events: Vec<SimEvent>,
+}
+
+impl Simulation {
+    pub fn new(size: Vec2i, worlds: Vec<World>, players: PlayerSetup) -> Self {
+        Simulation::with_rules(size, worlds, players, MatchRules::default())
+    }
+
+    pub fn with_rules(
+        size: Vec2i,
+        worlds: Vec<World>,
+        players: PlayerSetup,
+        rules: MatchRules,
+    ) -> Self {
+        let mut sim = Self {
+            size,
+            time: 0,
+            worlds,
+            squadrons: Vec::new(),
+            next_squadron_id: 0,
+            players,
+            surrendered: Vec::new(),
+            rules,
+            result: None,
+            events: Vec::new(),
+        };
+        sim.update_orbits();
+        sim
+    }
+
+    pub fn rules(&self) -> &MatchRules {
+        &self.rules
+    }
+
+    pub fn players(&self) -> &PlayerSetup {
+        &self.players
+    }
+
+    /// Events of the most recent tick.
+    pub fn events(&self) -> &[SimEvent] {
+        &self.events
+    }
+
+    pub fn has_surrendered(&self, team: Team) -> bool {
+        self.surrendered.contains(&team)
+    }
+
+    /// Set once the match is over, after that ticks no longer change anything.
+    pub fn result(&self) -> Option<&MatchResult> {
+        self.result.as_ref()
+    }
+
+    /// Ships on worlds plus ships in flight, used to decide timed matches.
+    pub fn score(&self, team: Team) -> i32 {
+        let on_worlds: i32 = self
+            .worlds
+            .iter()
+            .filter(|world| world.team == team)
+            .map(|world| world.ship_count)
+            .sum();
+        let in_flight: i32 = self
+            .squadrons
+            .iter()
+            .filter(|squadron| squadron.team == team)
+            .map(|squadron| squadron.ship_count)
+            .sum();
+
+        on_worlds + in_flight
+    }
+
+    pub fn is_eliminated(&self, team: Team) -> bool {
+        !self.worlds.iter().any(|world| world.team == team)
+            && !self.squadrons.iter().any(|squadron| squadron.team == team)
+    }
+
+    /// Still in the match, given the victory rules.
+    pub fn is_playing(&self, team: Team) -> bool {
+        self.players.contains(team)
+            && !self.has_surrendered(team)
+            && !(self.rules.victory.elimination && self.is_eliminated(team))
+    }
+
+    pub fn size(&self) -> Vec2i {
+        self.size
+    }
+
+    pub fn time(&self) -> i32 {
+        self.time
+    }
+
+    /// Where a world will be `ticks` from now.
+    pub fn world_pos_in(&self, id: WorldId, ticks: i32) -> Result<Vec2i, SimulationError> {
+        let world = self.world(id)?;
+        Ok(orbit::position_at(&self.worlds, world, self.time + ticks))
+    }
+
+    pub fn worlds(&self) -> &[World] {
+        &self.worlds
+    }
+
+    pub fn squadrons(&self) -> &[Squadron] {
+        &self.squadrons
+    }
+
+    pub fn next_squadron_id(&self) -> u32 {
+        self.next_squadron_id
+    }
+
+    /// Hash of the complete state. Two peers that ran the same ticks must get the same value.
+    pub fn checksum(&self) -> u64 {
+        let bytes = bincode::serialize(self).expect("Failed to serialize simulation");
+        fnv1a(&bytes)
+    }
+
+    pub fn world(&self, id: WorldId) -> Result<&World, SimulationError> {
+        self.worlds
+            .iter()
+            .find(|world| world.id == id)
+            .ok_or(SimulationError::UnknownWorld(id))
+    }
+
+    fn world_mut(&mut self, id: WorldId) -> Result<&mut World, SimulationError> {
+        self.worlds
+            .iter_mut()
+            .find(|world| world.id == id)
+            .ok_or(SimulationError::UnknownWorld(id))
+    }
+
+    pub fn squadron(&self, id: u32) -> Result<&Squadron, SimulationError> {
+        self.squadrons
+            .iter()
+            .find(|squadron| squadron.id == id)
+            .ok_or(SimulationError::UnknownSquadron(id))
+    }
+
+    /// Either applies the whole action or, if any world or squadron in it does not exist or
+    /// is not owned by the player, nothing at all.
+    pub fn handle_player_move(
+        &mut self,
+        player_action: &PlayerAction,
+        player_team: Team,
+    ) -> Result<(), SimulationError> {
+        if !self.players.contains(player_team) {
+            return Err(SimulationError::UnknownPlayer(player_team));
+        }
+
+        match player_action {
+            PlayerAction::None => {}
+            PlayerAction::Surrender => {
+                if self.rules.victory.allow_surrender && !self.has_surrendered(player_team) {
+                    self.surrender(player_team);
+                }
+            }
+            PlayerAction::Attack(attack) => {
+                self.world(attack.target)?;
+                if attack.sources.is_empty() {
+                    return Err(SimulationError::NoAttackSources);
+                }
+                if attack.sources.contains(&attack.target) {
+                    return Err(SimulationError::AttackFromTarget(attack.target));
+                }
+                for source in &attack.sources {
+                    if self.world(*source)?.team != player_team {
+                        return Err(SimulationError::NotOwner {
+                            world: *source,
+                            team: player_team,
+                        });
+                    }
+                }
+
+                for source in &attack.sources {
+                    let source_world = self.world(*source)?;
+
+                    let ship_count = attack.ratio.ships_to_send(source_world.ship_count);
+                    if ship_count == 0 {
+                        continue;
+                    }
+
+                    let start_pos = FixedVec2::from_ivec(source_world.pos);
+                    let (target_pos, distance) = orbit::intercept(
+                        &self.worlds,
+                        source_world.pos,
+                        self.world(attack.target)?,
+                        self.time,
+                        MAX_INTERCEPT_TICKS,
+                    );
+                    self.events.push(SimEvent::SquadronLaunched {
+                        squadron: self.next_squadron_id,
+                        team: player_team,
+                        source: *source,
+                        target: attack.target,
+                        ship_count,
+                    });
+                    self.squadrons.push(Squadron {
+                        id: self.next_squadron_id,
+                        start_pos,
+                        pos: start_pos,
+                        ship_count,
+                        team: player_team,
+                        source_world: *source,
+                        dest_world: attack.target,
+                        target_pos: FixedVec2::from_ivec(target_pos),
+                        distance_in_ticks: distance,
+                        travel_in_ticks: 0,
+                    });
+                    self.next_squadron_id += 1;
+
+                    self.world_mut(*source)?.ship_count -= ship_count;
+                }
+            }
+            PlayerAction::Redirect(redirect) => {
+                let target = self.world(redirect.target)?;
+                for id in &redirect.squadrons {
+                    if self.squadron(*id)?.team != player_team {
+                        return Err(SimulationError::NotSquadronOwner {
+                            squadron: *id,
+                            team: player_team,
+                        });
+                    }
+                }
+
+                // Every squadron starts a fresh straight line from wherever it is right now.
+                let mut paths = Vec::new();
+                for id in &redirect.squadrons {
+                    let from = self.squadron(*id)?.pos.round();
+                    paths.push(orbit::intercept(&self.worlds, from, target, self.time, MAX_INTERCEPT_TICKS));
+                }
+
+                for (id, (target_pos, distance)) in redirect.squadrons.iter().zip(paths) {
+                    let squadron = self
+                        .squadrons
+                        .iter_mut()
+                        .find(|squadron| squadron.id == *id)
+                        .ok_or(SimulationError::UnknownSquadron(*id))?;
+                    squadron.start_pos = squadron.pos;
+                    squadron.dest_world = redirect.target;
+                    squadron.target_pos = FixedVec2::from_ivec(target_pos);
+                    squadron.distance_in_ticks = distance;
+                    squadron.travel_in_ticks = 0;
+
+                    self.events.push(SimEvent::SquadronRedirected {
+                        squadron: *id,
+                        team: player_team,
+                        target: redirect.target,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Enemy squadrons whose paths met this tick fight, `previous_positions` holds where each
+    /// squadron started the tick. Pairs are resolved in launch order, a squadron can fight more
+    /// than once as long as it has ships left.
+    fn intercept_squadrons(&mut self, previous_positions: &[FixedVec2], rules: InterceptionRules) {
+        let range = Fixed::from_int(rules.range);
+        for i in 0..self.squadrons.len() {
+            for j in i + 1..self.squadrons.len() {
+                let (a, b) = (&self.squadrons[i], &self.squadrons[j]);
+                if a.ship_count == 0
+                    || b.ship_count == 0
+                    || a.team == b.team
+                    || self.players.are_allied(a.team, b.team)
+                    || !paths_meet(previous_positions[i], a.pos, previous_positions[j], b.pos, range)
+                {
+                    continue;
+                }
+
+                let ships_lost = a.ship_count.min(b.ship_count);
+                self.events.push(SimEvent::SquadronsClashed {
+                    first: a.id,
+                    first_team: a.team,
+                    second: b.id,
+                    second_team: b.team,
+                    ships_lost,
+                    pos: FixedVec2::new(
+                        Fixed::from_raw((a.pos.x.raw() + b.pos.x.raw()) / 2),
+                        Fixed::from_raw((a.pos.y.raw() + b.pos.y.raw()) / 2),
+                    )
+                    .round(),
+                });
+                self.squadrons[i].ship_count -= ships_lost;
+                self.squadrons[j].ship_count -= ships_lost;
+            }
+        }
+
+        self.squadrons.retain(|squadron| squadron.ship_count > 0);
+    }
+
+    fn update_orbits(&mut self) {
+        let positions: Vec<Vec2i> = self
+            .worlds
+            .iter()
+            .map(|world| orbit::position_at(&self.worlds, world, self.time))
+            .collect();
+        for (world, pos) in self.worlds.iter_mut().zip(positions) {
+            world.pos = pos;
+        }
+    }
+
+    /// A player that gives up leaves their worlds behind as neutrals, their fleets in flight are lost.
+    fn surrender(&mut self, team: Team) {
+        self.surrendered.push(team);
+
+        for world in &mut self.worlds {
+            if world.team == team {
+                world.team = Team::Neutral;
+            }
+        }
+        self.squadrons.retain(|squadron| squadron.team != team);
+    }
+
+    /// Owned worlds grow up to their capacity, anything above it decays back down. Neutral
+    /// worlds never grow but do decay.
+    fn produce(&mut self, tick_number: i32) {
+        let production = &self.rules.production;
+        let produce_now = tick_number % production.interval_ticks == 0;
+        if produce_now {
+            self.events.push(SimEvent::ProductionTick);
+        }
+        let decay = production
+            .decay
+            .filter(|decay| tick_number % decay.interval_ticks == 0);
+
+        for world in &mut self.worlds {
+            let world_production = production.for_size(world.size);
+
+            if produce_now && !world.team.is_neutral() && world.ship_count < world_production.capacity {
+                world.ship_count = (world.ship_count + world_production.ships_per_interval)
+                    .min(world_production.capacity);
+            }
+
+            if let Some(decay) = decay {
+                if world.ship_count > world_production.capacity {
+                    world.ship_count = (world.ship_count - decay.ships_per_interval)
+                        .max(world_production.capacity);
+                }
+            }
+        }
+    }
+
+    pub fn tick(&mut self, tick: &Tick) {
+        if self.result.is_some() {
+            return;
+        }
+
+        self.events.clear();
+        let surrendered_before = self.surrendered.len();
+        let playing_before: Vec<Team> = self
+            .players
+            .players()
+            .filter(|team| self.is_playing(*team))
+            .collect();
+
+        // A bad action is dropped the same way on every peer, so the match stays in sync.
+        let players: Vec<Team> = self.players.players().collect();
+        for team in players {
+            for action in tick.player_actions(team) {
+                let _ = self.handle_player_move(action, team);
+            }
+        }
+
+        self.produce(tick.tick_number);
+
+        self.time += 1;
+        self.update_orbits();
+
+        let mut previous_positions = Vec::with_capacity(self.squadrons.len());
+        for squadron in &mut self.squadrons {
+            previous_positions.push(squadron.pos);
+            squadron.travel_in_ticks += 1;
+            squadron.pos = FixedVec2::lerp_ratio(
+                squadron.start_pos,
+                squadron.target_pos,
+                squadron.travel_in_ticks as i64,
+                squadron.distance_in_ticks as i64,
+            );
+        }
+
+        if let Some(interception) = self.rules.interception {
+            self.intercept_squadrons(&previous_positions, interception);
+        }
+
+        let mut arrivals: Vec<(WorldId, Arrival)> = Vec::new();
+        let mut arrived_squadrons: Vec<SimEvent> = Vec::new();
+        for squadron in &self.squadrons {
+            if squadron.travel_in_ticks == squadron.distance_in_ticks {
+                arrived_squadrons.push(SimEvent::SquadronArrived {
+                    squadron: squadron.id,
+                    team: squadron.team,
+                    world: squadron.dest_world,
+                    ship_count: squadron.ship_count,
+                });
+                arrivals.push((
+                    squadron.dest_world,
+                    Arrival {
+                        team: squadron.team,
+                        ship_count: squadron.ship_count,
+                    },
+                ));
+            }
+        }
+
+        self.squadrons
+            .retain(|squadron| squadron.travel_in_ticks < squadron.distance_in_ticks);
+        self.events.append(&mut arrived_squadrons);
+
+        for world in &mut self.worlds {
+            let world_arrivals: Vec<Arrival> = arrivals
+                .iter()
+                .filter(|(world_id, _)| *world_id == world.id)
+                .map(|(_, arrival)| *arrival)
+                .collect();
+
+            if !world_arrivals.is_empty() {
+                let outcome = resolve_arrivals(world, &world_arrivals, &self.players);
+                self.events.push(match outcome {
+                    CombatOutcome::Reinforced => SimEvent::WorldReinforced {
+                        world: world.id,
+                        team: world.team,
+                        ship_count: world.ship_count,
+                    },
+                    CombatOutcome::Held => SimEvent::WorldDefended {
+                        world: world.id,
+                        team: world.team,
+                        ship_count: world.ship_count,
+                    },
+                    CombatOutcome::Captured { previous } => SimEvent::WorldCaptured {
+                        world: world.id,
+                        team: world.team,
+                        previous,
+                        ship_count: world.ship_count,
+                    },
+                    CombatOutcome::Neutralized { previous } => SimEvent::WorldNeutralized {
+                        world: world.id,
+                        previous,
+                    },
+                });
+            }
+        }
+
+        for team in playing_before {
+            if !self.is_playing(team) {
+                self.events.push(SimEvent::PlayerEliminated { team });
+            }
+        }
+
+        let someone_surrendered = self.surrendered.len() > surrendered_before;
+        self.result = self.check_victory(tick, someone_surrendered);
+    }
+
+    fn check_victory(&self, tick: &Tick, someone_surrendered: bool) -> Option<MatchResult> {
+        let end = |winners: Vec<Team>, reason: MatchEndReason| {
+            Some(MatchResult {
+                winners,
+                reason,
+                tick_number: tick.tick_number,
+            })
+        };
+
+        let mut alliances: Vec<u8> = self
+            .players
+            .players()
+            .filter(|team| self.is_playing(*team))
+            .filter_map(|team| self.players.alliance(team))
+            .collect();
+        alliances.sort_unstable();
+        alliances.dedup();
+
+        // The last alliance standing wins, if everyone gave up or died on the same tick it is a draw.
+        if alliances.len() <= 1 {
+            let winners = match alliances.first() {
+                Some(alliance) => self.alliance_members(*alliance),
+                None => Vec::new(),
+            };
+            let reason = if someone_surrendered {
+                MatchEndReason::Surrender
+            } else {
+                MatchEndReason::Elimination
+            };
+            return end(winners, reason);
+        }
+
+        if let Some(limit) = self.rules.victory.time_limit_ticks {
+            if tick.tick_number + 1 >= limit {
+                let alliance_score = |alliance: u8| -> i32 {
+                    self.alliance_members(alliance)
+                        .iter()
+                        .map(|team| self.score(*team))
+                        .sum()
+                };
+
+                let best = alliances.iter().map(|a| alliance_score(*a)).max().unwrap_or(0);
+                let leaders: Vec<u8> = alliances
+                    .iter()
+                    .copied()
+                    .filter(|alliance| alliance_score(*alliance) == best)
+                    .collect();
+                let winners = if leaders.len() == 1 {
+                    self.alliance_members(leaders[0])
+                } else {
+                    Vec::new()
+                };
+                return end(winners, MatchEndReason::TimeLimit);
+            }
+        }
+
+        None
+    }
+
+    fn alliance_members(&self, alliance: u8) -> Vec<Team> {
+        self.players
+            .players()
+            .filter(|team| self.players.alliance(*team) == Some(alliance))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{MatchStats, SimEventListener};
+    use crate::test_util::world;
+
+    fn two_world_simulation() -> Simulation {
+        Simulation::new(
+            Vec2i::new(100, 100),
+            vec![
+                World {
+                    id: WorldId(0),
+                    pos: Vec2i::new(0, 0),
+                    ship_count: 20,
+                    size: PlanetSize::Medium,
+                    team: Team::player(0),
+                    orbit: None,
+                },
+                World {
+                    id: WorldId(1),
+                    pos: Vec2i::new(30, 40),
+                    ship_count: 5,
+                    size: PlanetSize::Small,
+                    team: Team::player(1),
+                    orbit: None,
+                },
+            ],
+            PlayerSetup::free_for_all(2).unwrap(),
+        )
+    }
+
+    fn empty_tick(tick_number: i32) -> Tick {
+        Tick::new(tick_number, 2)
+    }
+
+    fn attack(source: u32, target: u32) -> PlayerAction {
+        PlayerAction::Attack(PlayerActionAttack {
+            sources: vec![WorldId(source)],
+            target: WorldId(target),
+            ratio: SendRatio::All,
+        })
+    }
+
+    fn tick_with_moves(tick_number: i32, player_moves: Vec<PlayerAction>) -> Tick {
+        Tick {
+            tick_number,
+            player_actions: player_moves.into_iter().map(|action| vec![action]).collect(),
+        }
+    }
+
+    #[test]
+    fn attack_captures_world() {
+        let mut sim = two_world_simulation();
+
+        sim.tick(&tick_with_moves(1, vec![attack(0, 1)]));
+
+        assert_eq!(sim.worlds()[0].ship_count, 0);
+        assert_eq!(sim.squadrons().len(), 1);
+        assert_eq!(sim.squadrons()[0].distance_in_ticks, 50);
+
+        for tick_number in 2..=50 {
+            sim.tick(&empty_tick(tick_number));
+        }
+
+        assert!(sim.squadrons().is_empty());
+        assert_eq!(sim.worlds()[1].team, Team::player(0));
+        assert_eq!(sim.worlds()[1].ship_count, 14);
+    }
+
+    #[test]
+    fn squadrons_meet_orbiting_worlds() {
+        let mut moving = world(1, 0, 5, Team::player(1));
+        moving.orbit = Some(Orbit {
+            center: crate::orbit::OrbitCenter::Point(Vec2i::new(0, 0)),
+            offset: Vec2i::new(60, 0),
+            period_ticks: 120,
+        });
+        let mut sim = Simulation::new(
+            Vec2i::new(300, 300),
+            vec![world(0, -100, 20, Team::player(0)), moving],
+            PlayerSetup::free_for_all(2).unwrap(),
+        );
+        assert_eq!(sim.worlds()[1].pos, Vec2i::new(60, 0));
+
+        sim.tick(&tick_with_moves(0, vec![attack(0, 1)]));
+        let squadron = sim.squadrons()[0].clone();
+        let arrival_pos = sim.world_pos_in(WorldId(1), squadron.distance_in_ticks - 1).unwrap();
+        assert_eq!(squadron.target_pos.round(), arrival_pos);
+
+        for tick_number in 1..squadron.distance_in_ticks {
+            assert_eq!(sim.worlds()[1].team, Team::player(1));
+            sim.tick(&empty_tick(tick_number));
+        }
+
+        assert!(sim.squadrons().is_empty());
+        assert_eq!(sim.worlds()[1].pos, arrival_pos);
+        assert_eq!(sim.worlds()[1].team, Team::player(0));
+    }
+
+    #[test]
+    fn all_actions_of_a_tick_run_in_order() {
+        let mut sim = two_world_simulation();
+        let half = |source, target| {
+            PlayerAction::Attack(PlayerActionAttack {
+                sources: vec![WorldId(source)],
+                target: WorldId(target),
+                ratio: SendRatio::Half,
+            })
+        };
+
+        let mut tick = empty_tick(1);
+        assert!(tick.push_player_action(Team::player(0), half(0, 1)));
+        assert!(tick.push_player_action(Team::player(0), half(0, 1)));
+        assert!(tick.push_player_action(Team::player(1), half(1, 0)));
+        sim.tick(&tick);
+
+        // Half of 20, then half of the 10 that are left.
+        let ship_counts: Vec<(Team, i32)> = sim
+            .squadrons()
+            .iter()
+            .map(|squadron| (squadron.team, squadron.ship_count))
+            .collect();
+        assert_eq!(
+            ship_counts,
+            vec![(Team::player(0), 10), (Team::player(0), 5), (Team::player(1), 2)]
+        );
+    }
+
+    #[test]
+    fn actions_per_tick_are_bounded() {
+        let mut tick = empty_tick(0);
+        for _ in 0..MAX_ACTIONS_PER_TICK {
+            assert!(tick.push_player_action(Team::player(0), attack(0, 1)));
+        }
+        assert!(!tick.push_player_action(Team::player(0), attack(0, 1)));
+        assert!(tick.push_player_action(Team::player(0), PlayerAction::None));
+        assert_eq!(tick.player_actions(Team::player(0)).len(), MAX_ACTIONS_PER_TICK);
+
+        // Ticks from elsewhere can hold more, only the first few count.
+        tick.player_actions[1] = vec![attack(1, 0); MAX_ACTIONS_PER_TICK * 2];
+        assert_eq!(tick.player_actions(Team::player(1)).len(), MAX_ACTIONS_PER_TICK);
+        assert!(tick.player_actions(Team::Neutral).is_empty());
+    }
+
+    #[test]
+    fn squadrons_can_be_redirected_mid_flight() {
+        let mut sim = Simulation::new(
+            Vec2i::new(300, 100),
+            vec![
+                world(0, 0, 20, Team::player(0)),
+                world(1, 100, 5, Team::player(1)),
+                world(2, -50, 3, Team::Neutral),
+            ],
+            PlayerSetup::free_for_all(2).unwrap(),
+        );
+        sim.tick(&tick_with_moves(0, vec![attack(0, 1)]));
+        for tick_number in 1..=10 {
+            sim.tick(&empty_tick(tick_number));
+        }
+        let id = sim.squadrons()[0].id;
+        assert_eq!(sim.squadrons()[0].pos.round(), Vec2i::new(11, 0));
+
+        let redirect = |target| {
+            PlayerAction::Redirect(PlayerActionRedirect {
+                squadrons: vec![id],
+                target: WorldId(target),
+            })
+        };
+        assert!(matches!(
+            sim.handle_player_move(&redirect(2), Team::player(1)),
+            Err(SimulationError::NotSquadronOwner { .. })
+        ));
+        assert_eq!(sim.squadrons()[0].dest_world, WorldId(1));
+
+        sim.tick(&tick_with_moves(11, vec![redirect(2)]));
+        let squadron = sim.squadrons()[0].clone();
+        assert_eq!(squadron.dest_world, WorldId(2));
+        assert_eq!(squadron.distance_in_ticks, 61);
+        assert!(sim
+            .events()
+            .iter()
+            .any(|event| matches!(event, SimEvent::SquadronRedirected { target: WorldId(2), .. })));
+
+        for tick_number in 12..11 + squadron.distance_in_ticks {
+            sim.tick(&empty_tick(tick_number));
+        }
+        assert!(sim.squadrons().is_empty());
+        assert_eq!(sim.worlds()[2].team, Team::player(0));
+        assert_eq!(sim.worlds()[1].team, Team::player(1));
+    }
+
+    #[test]
+    fn enemy_squadrons_fight_when_their_paths_cross() {
+        let play = |interception| {
+            let rules = MatchRules {
+                interception,
+                ..MatchRules::default()
+            };
+            let mut sim = Simulation::with_rules(
+                Vec2i::new(200, 100),
+                vec![world(0, 0, 20, Team::player(0)), world(1, 100, 8, Team::player(1))],
+                PlayerSetup::free_for_all(2).unwrap(),
+                rules,
+            );
+            sim.tick(&tick_with_moves(0, vec![attack(0, 1), attack(1, 0)]));
+
+            let mut clashes = Vec::new();
+            for tick_number in 1..60 {
+                sim.tick(&empty_tick(tick_number));
+                clashes.extend(
+                    sim.events()
+                        .iter()
+                        .filter(|event| matches!(event, SimEvent::SquadronsClashed { .. }))
+                        .cloned(),
+                );
+            }
+            (sim, clashes)
+        };
+
+        let (sim, clashes) = play(None);
+        assert!(clashes.is_empty());
+        assert_eq!(sim.squadrons().len(), 2);
+
+        let (sim, clashes) = play(Some(InterceptionRules::default()));
+        assert_eq!(clashes.len(), 1);
+        assert!(matches!(
+            clashes[0],
+            SimEvent::SquadronsClashed {
+                first: 0,
+                second: 1,
+                ships_lost: 8,
+                pos,
+                ..
+            } if (pos.x - 50).abs() <= InterceptionRules::default().range
+        ));
+        assert_eq!(sim.squadrons().len(), 1);
+        assert_eq!(sim.squadrons()[0].team, Team::player(0));
+        assert_eq!(sim.squadrons()[0].ship_count, 12);
+    }
+
+    #[test]
+    fn send_ratio_splits_the_fleet() {
+        let mut sim = two_world_simulation();
+
+        let attack = PlayerAction::Attack(PlayerActionAttack {
+            sources: vec![WorldId(0)],
+            target: WorldId(1),
+            ratio: SendRatio::ThreeQuarters,
+        });
+        sim.handle_player_move(&attack, Team::player(0)).unwrap();
+
+        // 75% of 20 is 15, the other 5 stay home.
+        assert_eq!(sim.squadrons()[0].ship_count, 15);
+        assert_eq!(sim.worlds()[0].ship_count, 5);
+
+        assert_eq!(SendRatio::Quarter.ships_to_send(7), 1);
+        assert_eq!(SendRatio::Half.ships_to_send(7), 3);
+        assert_eq!(SendRatio::Quarter.ships_to_send(1), 1);
+        assert_eq!(SendRatio::All.ships_to_send(0), 0);
+    }
+
+    #[test]
+    fn empty_worlds_send_nothing() {
+        let mut sim = two_world_simulation();
+        sim.world_mut(WorldId(0)).unwrap().ship_count = 0;
+
+        sim.handle_player_move(&attack(0, 1), Team::player(0)).unwrap();
+
+        assert!(sim.squadrons().is_empty());
+    }
+
+    #[test]
+    fn production_depends_on_size() {
+        let mut sim = two_world_simulation();
+
+        for tick_number in 0..61 {
+            sim.tick(&empty_tick(tick_number));
+        }
+
+        // Three production ticks, medium worlds make two ships each time and small ones one.
+        assert_eq!(sim.worlds()[0].ship_count, 26);
+        assert_eq!(sim.worlds()[1].ship_count, 8);
+    }
+
+    #[test]
+    fn production_stops_at_capacity_and_decays_above_it() {
+        let mut sim = Simulation::new(
+            Vec2i::new(100, 100),
+            vec![
+                world(0, -40, 29, Team::player(0)),
+                world(1, 0, 35, Team::player(1)),
+                world(2, 40, 40, Team::Neutral),
+            ],
+            PlayerSetup::free_for_all(2).unwrap(),
+        );
+
+        sim.tick(&empty_tick(0));
+        sim.tick(&empty_tick(30));
+
+        // Small worlds hold 30 ships.
+        assert_eq!(sim.worlds()[0].ship_count, 30);
+        assert_eq!(sim.worlds()[1].ship_count, 33);
+        assert_eq!(sim.worlds()[2].ship_count, 38);
+
+        let mut rules = MatchRules::default();
+        rules.production.decay = None;
+        let mut sim = Simulation::with_rules(
+            sim.size(),
+            vec![world(0, 0, 35, Team::player(0)), world(1, 40, 5, Team::player(1))],
+            PlayerSetup::free_for_all(2).unwrap(),
+            rules,
+        );
+        sim.tick(&empty_tick(0));
+        assert_eq!(sim.worlds()[0].ship_count, 35);
+    }
+
+    fn scripted_action(sim: &Simulation, team: Team, ratio: SendRatio) -> PlayerAction {
+        let sources: Vec<WorldId> = sim
+            .worlds()
+            .iter()
+            .filter(|world| world.team == team)
+            .map(|world| world.id)
+            .collect();
+
+        match sim.worlds().iter().find(|world| world.team != team && !world.team.is_neutral()) {
+            Some(target) if !sources.is_empty() => PlayerAction::Attack(PlayerActionAttack {
+                sources,
+                target: target.id,
+                ratio,
+            }),
+            _ => PlayerAction::None,
+        }
+    }
+
+    #[test]
+    fn scripted_match_is_bit_identical() {
+        // The expected hash is the same in debug and release and on every target, if this
+        // changes without a rule change then something non deterministic crept into the simulation.
+        let size = Vec2i::new(500, 300);
+        let mut sim = Simulation::new(
+            size,
+            crate::map_gen::generate_random(size, 42, 2),
+            PlayerSetup::free_for_all(2).unwrap(),
+        );
+
+        for tick_number in 0..3000 {
+            let mut tick = Tick::new(tick_number, 2);
+            if tick_number % 40 == 0 {
+                let action = scripted_action(&sim, Team::player(0), SendRatio::All);
+                tick.push_player_action(Team::player(0), action);
+            }
+            if tick_number % 55 == 0 {
+                let action = scripted_action(&sim, Team::player(1), SendRatio::Half);
+                tick.push_player_action(Team::player(1), action);
+            }
+
+            sim.tick(&tick);
+        }
+
+        assert_eq!(sim.checksum(), 15331358948687640524);
+    }
+
+    #[test]
+    fn elimination_ends_the_match() {
+        let mut sim = two_world_simulation();
+
+        sim.tick(&tick_with_moves(1, vec![attack(0, 1)]));
+        assert!(sim.result().is_none());
+
+        for tick_number in 2..=50 {
+            sim.tick(&empty_tick(tick_number));
+        }
+
+        assert_eq!(
+            sim.result(),
+            Some(&MatchResult {
+                winners: vec![Team::player(0)],
+                reason: MatchEndReason::Elimination,
+                tick_number: 50,
+            })
+        );
+
+        // Nothing moves once the match is decided.
+        let checksum = sim.checksum();
+        sim.tick(&empty_tick(60));
+        assert_eq!(sim.checksum(), checksum);
+    }
+
+    #[test]
+    fn surrender() {
+        let mut sim = two_world_simulation();
+        sim.tick(&tick_with_moves(1, vec![PlayerAction::None, PlayerAction::Surrender]));
+        assert_eq!(sim.result().unwrap().winners, vec![Team::player(0)]);
+        assert_eq!(sim.result().unwrap().reason, MatchEndReason::Surrender);
+        assert_eq!(sim.worlds()[1].team, Team::Neutral);
+
+        let mut sim = two_world_simulation();
+        sim.tick(&tick_with_moves(1, vec![PlayerAction::Surrender, PlayerAction::Surrender]));
+        assert!(sim.result().unwrap().winners.is_empty());
+
+        let mut rules = MatchRules::default();
+        rules.victory.allow_surrender = false;
+        let base = two_world_simulation();
+        let mut sim = Simulation::with_rules(
+            base.size(),
+            base.worlds().to_vec(),
+            base.players().clone(),
+            rules,
+        );
+        sim.tick(&tick_with_moves(1, vec![PlayerAction::Surrender]));
+        assert!(sim.result().is_none());
+    }
+
+    #[test]
+    fn time_limit_picks_highest_score() {
+        let mut rules = MatchRules::default();
+        rules.victory.time_limit_ticks = Some(10);
+        let base = two_world_simulation();
+        let players = base.players().clone();
+        let mut sim =
+            Simulation::with_rules(base.size(), base.worlds().to_vec(), players.clone(), rules.clone());
+
+        for tick_number in 0..9 {
+            sim.tick(&empty_tick(tick_number));
+        }
+        assert!(sim.result().is_none());
+
+        sim.tick(&empty_tick(9));
+        assert_eq!(
+            sim.result(),
+            Some(&MatchResult {
+                winners: vec![Team::player(0)],
+                reason: MatchEndReason::TimeLimit,
+                tick_number: 9,
+            })
+        );
+
+        let mut worlds = base.worlds().to_vec();
+        worlds[1].ship_count = worlds[0].ship_count;
+        worlds[1].size = worlds[0].size;
+        let mut sim = Simulation::with_rules(base.size(), worlds, players, rules);
+        for tick_number in 0..10 {
+            sim.tick(&empty_tick(tick_number));
+        }
+        assert!(sim.result().unwrap().winners.is_empty());
+    }
+
+    #[test]
+    fn unknown_world_is_an_error() {
+        let mut sim = two_world_simulation();
+
+        let attack = PlayerAction::Attack(PlayerActionAttack {
+            sources: vec![WorldId(0)],
+            target: WorldId(7),
+            ratio: SendRatio::All,
+        });
+        assert_eq!(
+            sim.handle_player_move(&attack, Team::player(0)),
+            Err(SimulationError::UnknownWorld(WorldId(7)))
+        );
+
+        let attack = PlayerAction::Attack(PlayerActionAttack {
+            sources: vec![WorldId(0), WorldId(9)],
+            target: WorldId(1),
+            ratio: SendRatio::All,
+        });
+        assert_eq!(
+            sim.handle_player_move(&attack, Team::player(0)),
+            Err(SimulationError::UnknownWorld(WorldId(9)))
+        );
+
+        assert!(sim.squadrons().is_empty());
+        assert_eq!(sim.worlds()[0].ship_count, 20);
+        assert_eq!(sim.world(WorldId(1)).unwrap().ship_count, 5);
+    }
+
+    #[test]
+    fn ticks_report_events() {
+        let mut sim = two_world_simulation();
+
+        sim.tick(&tick_with_moves(1, vec![attack(0, 1)]));
+        assert_eq!(
+            sim.events(),
+            &[SimEvent::SquadronLaunched {
+                squadron: 0,
+                team: Team::player(0),
+                source: WorldId(0),
+                target: WorldId(1),
+                ship_count: 20,
+            }]
+        );
+
+        for tick_number in 2..30 {
+            sim.tick(&empty_tick(tick_number));
+        }
+        sim.tick(&empty_tick(30));
+        assert_eq!(sim.events(), &[SimEvent::ProductionTick]);
+
+        for tick_number in 31..50 {
+            sim.tick(&empty_tick(tick_number));
+        }
+        sim.tick(&empty_tick(50));
+        assert_eq!(
+            sim.events(),
+            &[
+                SimEvent::SquadronArrived {
+                    squadron: 0,
+                    team: Team::player(0),
+                    world: WorldId(1),
+                    ship_count: 20,
+                },
+                SimEvent::WorldCaptured {
+                    world: WorldId(1),
+                    team: Team::player(0),
+                    previous: Team::player(1),
+                    ship_count: 14,
+                },
+                SimEvent::PlayerEliminated {
+                    team: Team::player(1)
+                },
+            ]
+        );
+
+        let mut stats = MatchStats::new(2);
+        for event in sim.events() {
+            stats.on_sim_event(50, event);
+        }
+        assert_eq!(stats.player(Team::player(0)).unwrap().worlds_captured, 1);
+        assert_eq!(stats.player(Team::player(1)).unwrap().eliminated_on_tick, Some(50));
+    }
+
+    #[test]
+    fn only_owned_worlds_can_attack() {
+        let mut sim = two_world_simulation();
+
+        assert_eq!(
+            sim.handle_player_move(&attack(1, 0), Team::player(0)),
+            Err(SimulationError::NotOwner {
+                world: WorldId(1),
+                team: Team::player(0)
+            })
+        );
+        assert_eq!(
+            sim.handle_player_move(&attack(0, 1), Team::Neutral),
+            Err(SimulationError::UnknownPlayer(Team::Neutral))
+        );
+        assert!(sim.squadrons().is_empty());
+    }
+
+    #[test]
+    fn attacks_need_sources_other_than_the_target() {
+        let mut sim = two_world_simulation();
+
+        let no_sources = PlayerAction::Attack(PlayerActionAttack {
+            sources: Vec::new(),
+            target: WorldId(1),
+            ratio: SendRatio::All,
+        });
+        assert_eq!(
+            sim.handle_player_move(&no_sources, Team::player(0)),
+            Err(SimulationError::NoAttackSources)
+        );
+        assert_eq!(
+            sim.handle_player_move(&attack(0, 0), Team::player(0)),
+            Err(SimulationError::AttackFromTarget(WorldId(0)))
+        );
+        assert!(sim.squadrons().is_empty());
+    }
+
+    #[test]
+    fn neutral_worlds_do_not_produce() {
+        let mut sim = Simulation::new(
+            Vec2i::new(100, 100),
+            vec![
+                world(0, -40, 10, Team::player(0)),
+                world(1, 0, 10, Team::Neutral),
+                world(2, 40, 10, Team::player(1)),
+            ],
+            PlayerSetup::free_for_all(2).unwrap(),
+        );
+
+        sim.tick(&empty_tick(0));
+
+        assert_eq!(sim.worlds()[0].ship_count, 11);
+        assert_eq!(sim.worlds()[1].ship_count, 10);
+        assert_eq!(sim.worlds()[2].ship_count, 11);
+    }
+
+    #[test]
+    fn free_for_all_goes_on_until_one_player_is_left() {
+        let mut sim = Simulation::new(
+            Vec2i::new(200, 100),
+            vec![
+                world(0, -60, 10, Team::player(0)),
+                world(1, 0, 20, Team::player(1)),
+                world(2, 60, 10, Team::player(2)),
+            ],
+            PlayerSetup::free_for_all(3).unwrap(),
+        );
+
+        sim.tick(&tick_with_moves(1, vec![PlayerAction::Surrender]));
+        assert!(sim.result().is_none());
+        assert!(!sim.is_playing(Team::player(0)));
+
+        sim.tick(&tick_with_moves(
+            2,
+            vec![PlayerAction::None, PlayerAction::None, PlayerAction::Surrender],
+        ));
+        assert_eq!(sim.result().unwrap().winners, vec![Team::player(1)]);
+    }
+
+    #[test]
+    fn allies_reinforce_and_win_together() {
+        // Players 0 and 2 against 1 and 3.
+        let players = PlayerSetup::teams(4, 2).unwrap();
+        assert!(players.are_allied(Team::player(0), Team::player(2)));
+        assert!(!players.are_allied(Team::player(0), Team::player(1)));
+        assert!(!players.are_allied(Team::Neutral, Team::Neutral));
+
+        let mut sim = Simulation::new(
+            Vec2i::new(200, 100),
+            vec![
+                world(0, -60, 10, Team::player(0)),
+                world(1, -30, 10, Team::player(1)),
+                world(2, 0, 10, Team::player(2)),
+                world(3, 30, 10, Team::player(3)),
+            ],
+            players,
+        );
+
+        sim.tick(&tick_with_moves(1, vec![attack(0, 2)]));
+        for tick_number in 2..=60 {
+            sim.tick(&empty_tick(tick_number));
+        }
+        assert_eq!(sim.worlds()[2].team, Team::player(2));
+        assert_eq!(sim.worlds()[2].ship_count, 22);
+
+        sim.tick(&tick_with_moves(
+            61,
+            vec![
+                PlayerAction::None,
+                PlayerAction::Surrender,
+                PlayerAction::None,
+                PlayerAction::Surrender,
+            ],
+        ));
+        assert_eq!(
+            sim.result().unwrap().winners,
+            vec![Team::player(0), Team::player(2)]
+        );
+    }
+
+    #[test]
+    fn player_setup_is_validated() {
+        assert_eq!(
+            PlayerSetup::free_for_all(1),
+            Err(SimulationError::InvalidPlayerCount(1))
+        );
+        assert_eq!(
+            PlayerSetup::free_for_all(9),
+            Err(SimulationError::InvalidPlayerCount(9))
+        );
+        assert_eq!(PlayerSetup::new(vec![1, 1, 1]), Err(SimulationError::SingleAlliance));
+        assert_eq!(PlayerSetup::free_for_all(8).unwrap().player_count(), 8);
+    }
+}
@@ -0,0 +1,14 @@
+use crate::math::Vec2i;
+use crate::simulation::{PlanetSize, Team, World, WorldId};
+
+/// A small world without an orbit on the x axis, tests place worlds on a line with it.
+pub fn world(id: u32, x: i32, ship_count: i32, team: Team) -> World {
+    World {
+        id: WorldId(id),
+        pos: Vec2i::new(x, 0),
+        ship_count,
+        size: PlanetSize::Small,
+        team,
+        orbit: None,
+    }
+}
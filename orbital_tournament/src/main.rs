@@ -0,0 +1,219 @@
+mod report;
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+
+use orbital_shared::ai::AiKind;
+use orbital_shared::controller::{self, PlayerController};
+use orbital_shared::map_gen::{self, MapGenSettings};
+use orbital_shared::math::Vec2i;
+use orbital_shared::rules::MatchRules;
+use orbital_shared::simulation::{PlayerSetup, Simulation};
+
+use report::{MatchRecord, TournamentReport};
+
+/*
+   Plays every built in AI against every other one on seeded fair maps and
+   writes the results, so rule changes can be compared with numbers instead of
+   gut feeling.
+
+   Usage: orbital_tournament [-maps n] [-seed n] [-threads n] [-rules file] [-out dir]
+
+   Every ordered pairing plays once per map, so each AI gets every seat on
+   every map. Matches are independent and deterministic, they are spread over
+   all cores and the report is built in match order, so the same arguments
+   always produce the same files.
+*/
+
+/// Same map size the client uses for random matches.
+const MAP_SIZE: Vec2i = map_gen::MATCH_MAP_SIZE;
+
+/// Rules without a time limit get this one, ten minutes at 24 ticks a second, so every match ends.
+const DEFAULT_TIME_LIMIT_TICKS: i32 = 24 * 60 * 10;
+
+struct Options {
+    maps: u64,
+    first_seed: u64,
+    threads: usize,
+    rules: MatchRules,
+    out_dir: PathBuf,
+}
+
+impl Options {
+    fn from_args() -> Result<Options, String> {
+        let args: Vec<String> = std::env::args().collect();
+        let mut options = Options {
+            maps: 100,
+            first_seed: 1,
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            rules: MatchRules::default(),
+            out_dir: PathBuf::from("tournament/"),
+        };
+
+        let mut i = 1;
+        while i < args.len() {
+            let value = args
+                .get(i + 1)
+                .ok_or_else(|| format!("Missing value for {}", args[i]))?;
+            match args[i].as_str() {
+                "-maps" => options.maps = value.parse().map_err(|_| "Map count must be a number")?,
+                "-seed" => options.first_seed = value.parse().map_err(|_| "Seed must be a number")?,
+                "-threads" => {
+                    options.threads = value.parse().map_err(|_| "Thread count must be a number")?
+                }
+                "-rules" => options.rules = MatchRules::load(value).map_err(|e| e.to_string())?,
+                "-out" => options.out_dir = PathBuf::from(value),
+                other => return Err(format!("Unknown argument {}", other)),
+            }
+            i += 2;
+        }
+
+        if options.rules.victory.time_limit_ticks.is_none() {
+            options.rules.victory.time_limit_ticks = Some(DEFAULT_TIME_LIMIT_TICKS);
+        }
+        options.threads = options.threads.max(1);
+        Ok(options)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MatchJob {
+    seed: u64,
+    seats: [AiKind; 2],
+}
+
+fn jobs(options: &Options) -> Vec<MatchJob> {
+    let mut jobs = Vec::new();
+    for seed in options.first_seed..options.first_seed + options.maps {
+        for first in AiKind::ALL {
+            for second in AiKind::ALL {
+                if first != second {
+                    jobs.push(MatchJob {
+                        seed,
+                        seats: [first, second],
+                    });
+                }
+            }
+        }
+    }
+    jobs
+}
+
+fn play_match(job: &MatchJob, rules: &MatchRules) -> MatchRecord {
+    let players = PlayerSetup::free_for_all(job.seats.len() as u32).expect("Two players are always valid");
+    let settings = MapGenSettings::for_players(players.player_count() as u32);
+    let worlds = map_gen::generate_symmetric(MAP_SIZE, job.seed, &settings)
+        .expect("The default map settings always fit the tournament map");
+    let mut sim = Simulation::with_rules(MAP_SIZE, worlds, players.clone(), rules.clone());
+    let mut controllers: Vec<Box<dyn PlayerController + Send>> =
+        job.seats.iter().map(|kind| kind.create()).collect();
+
+    let mut tick_number = 0;
+    let result = loop {
+        let tick = controller::collect_tick(&sim, tick_number, &mut controllers);
+        sim.tick(&tick);
+        tick_number += 1;
+
+        if let Some(result) = sim.result() {
+            break result.clone();
+        }
+    };
+
+    MatchRecord {
+        seed: job.seed,
+        seats: job.seats.to_vec(),
+        winners: result
+            .winners
+            .iter()
+            .filter_map(|team| team.index().map(|slot| job.seats[slot]))
+            .collect(),
+        winning_seats: result.winners.iter().filter_map(|team| team.index()).collect(),
+        reason: result.reason,
+        ticks: result.tick_number + 1,
+    }
+}
+
+fn run(options: &Options) -> Vec<MatchRecord> {
+    let jobs = Arc::new(jobs(options));
+    let next_job = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = channel();
+
+    let workers: Vec<_> = (0..options.threads)
+        .map(|_| {
+            let jobs = Arc::clone(&jobs);
+            let next_job = Arc::clone(&next_job);
+            let sender = sender.clone();
+            let rules = options.rules.clone();
+            thread::spawn(move || loop {
+                let index = next_job.fetch_add(1, Ordering::Relaxed);
+                let job = match jobs.get(index) {
+                    Some(job) => job,
+                    None => break,
+                };
+                if sender.send((index, play_match(job, &rules))).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut records: Vec<Option<MatchRecord>> = vec![None; jobs.len()];
+    for (done, (index, record)) in receiver.iter().enumerate() {
+        records[index] = Some(record);
+        if (done + 1) % 100 == 0 {
+            println!("{}/{} matches played", done + 1, jobs.len());
+        }
+    }
+
+    for worker in workers {
+        worker.join().expect("Tournament worker panicked");
+    }
+
+    records
+        .into_iter()
+        .map(|record| record.expect("Every match reports a result"))
+        .collect()
+}
+
+fn main() {
+    let options = match Options::from_args() {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            println!("Usage: orbital_tournament [-maps n] [-seed n] [-threads n] [-rules file] [-out dir]");
+            std::process::exit(1);
+        }
+    };
+
+    println!(
+        "Playing {} maps on {} threads, time limit {} ticks",
+        options.maps,
+        options.threads,
+        options.rules.victory.time_limit_ticks.unwrap_or(0)
+    );
+
+    let records = run(&options);
+    let report = TournamentReport::new(options.rules.clone(), records);
+
+    for ai in &report.ais {
+        println!(
+            "{:>10}: {:5.1}% wins, {} draws, average match {:.0} ticks",
+            ai.ai.name(),
+            ai.win_rate() * 100.0,
+            ai.draws,
+            ai.average_ticks()
+        );
+    }
+
+    match report.write_to_dir(&options.out_dir) {
+        Ok(_) => println!("Results written to {}", options.out_dir.display()),
+        Err(e) => {
+            println!("Failed to write results: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
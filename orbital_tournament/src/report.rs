@@ -0,0 +1,324 @@
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use orbital_shared::ai::AiKind;
+use orbital_shared::rules::MatchRules;
+use orbital_shared::simulation::MatchEndReason;
+
+/// How a single match went, seats are in player order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MatchRecord {
+    pub seed: u64,
+    pub seats: Vec<AiKind>,
+    /// Empty for a draw.
+    pub winners: Vec<AiKind>,
+    pub winning_seats: Vec<usize>,
+    pub reason: MatchEndReason,
+    pub ticks: i32,
+}
+
+impl MatchRecord {
+    pub fn is_draw(&self) -> bool {
+        self.winning_seats.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AiStats {
+    pub ai: AiKind,
+    pub matches: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+    pub total_ticks: i64,
+}
+
+impl AiStats {
+    pub fn win_rate(&self) -> f64 {
+        ratio(self.wins as i64, self.matches)
+    }
+
+    pub fn average_ticks(&self) -> f64 {
+        ratio(self.total_ticks, self.matches)
+    }
+}
+
+/// Results of one AI in the first seat against another in the second.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PairingStats {
+    pub first: AiKind,
+    pub second: AiKind,
+    pub matches: u32,
+    pub first_wins: u32,
+    pub second_wins: u32,
+    pub draws: u32,
+    pub total_ticks: i64,
+}
+
+/// Results of every match on one map, useful to spot maps that favour a seat.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapStats {
+    pub seed: u64,
+    pub matches: u32,
+    pub seat_wins: Vec<u32>,
+    pub draws: u32,
+    pub total_ticks: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TournamentReport {
+    pub rules: MatchRules,
+    pub match_count: u32,
+    pub average_ticks: f64,
+    pub ais: Vec<AiStats>,
+    pub pairings: Vec<PairingStats>,
+    pub maps: Vec<MapStats>,
+    pub matches: Vec<MatchRecord>,
+}
+
+fn ratio(value: i64, count: u32) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        value as f64 / count as f64
+    }
+}
+
+impl TournamentReport {
+    pub fn new(rules: MatchRules, matches: Vec<MatchRecord>) -> TournamentReport {
+        let mut ais: Vec<AiStats> = AiKind::ALL
+            .iter()
+            .map(|&ai| AiStats {
+                ai,
+                matches: 0,
+                wins: 0,
+                losses: 0,
+                draws: 0,
+                total_ticks: 0,
+            })
+            .collect();
+        let mut pairings: Vec<PairingStats> = Vec::new();
+        let mut maps: Vec<MapStats> = Vec::new();
+
+        for record in &matches {
+            for (seat, ai) in record.seats.iter().enumerate() {
+                let stats = ais
+                    .iter_mut()
+                    .find(|stats| stats.ai == *ai)
+                    .expect("Every AI has stats");
+                stats.matches += 1;
+                stats.total_ticks += record.ticks as i64;
+                if record.is_draw() {
+                    stats.draws += 1;
+                } else if record.winning_seats.contains(&seat) {
+                    stats.wins += 1;
+                } else {
+                    stats.losses += 1;
+                }
+            }
+
+            if let [first, second] = record.seats[..] {
+                let index = match pairings
+                    .iter()
+                    .position(|pairing| pairing.first == first && pairing.second == second)
+                {
+                    Some(index) => index,
+                    None => {
+                        pairings.push(PairingStats {
+                            first,
+                            second,
+                            matches: 0,
+                            first_wins: 0,
+                            second_wins: 0,
+                            draws: 0,
+                            total_ticks: 0,
+                        });
+                        pairings.len() - 1
+                    }
+                };
+                let pairing = &mut pairings[index];
+                pairing.matches += 1;
+                pairing.total_ticks += record.ticks as i64;
+                if record.is_draw() {
+                    pairing.draws += 1;
+                } else if record.winning_seats.contains(&0) {
+                    pairing.first_wins += 1;
+                } else {
+                    pairing.second_wins += 1;
+                }
+            }
+
+            let index = match maps.iter().position(|map| map.seed == record.seed) {
+                Some(index) => index,
+                None => {
+                    maps.push(MapStats {
+                        seed: record.seed,
+                        matches: 0,
+                        seat_wins: vec![0; record.seats.len()],
+                        draws: 0,
+                        total_ticks: 0,
+                    });
+                    maps.len() - 1
+                }
+            };
+            let map = &mut maps[index];
+            map.matches += 1;
+            map.total_ticks += record.ticks as i64;
+            if record.is_draw() {
+                map.draws += 1;
+            }
+            for &seat in &record.winning_seats {
+                if let Some(wins) = map.seat_wins.get_mut(seat) {
+                    *wins += 1;
+                }
+            }
+        }
+
+        let match_count = matches.len() as u32;
+        let total_ticks: i64 = matches.iter().map(|record| record.ticks as i64).sum();
+
+        TournamentReport {
+            rules,
+            match_count,
+            average_ticks: ratio(total_ticks, match_count),
+            ais,
+            pairings,
+            maps,
+            matches,
+        }
+    }
+
+    pub fn ais_csv(&self) -> String {
+        let mut csv = String::from("ai,matches,wins,losses,draws,win_rate,average_ticks\n");
+        for ai in &self.ais {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{:.4},{:.1}",
+                ai.ai.name(),
+                ai.matches,
+                ai.wins,
+                ai.losses,
+                ai.draws,
+                ai.win_rate(),
+                ai.average_ticks()
+            );
+        }
+        csv
+    }
+
+    pub fn pairings_csv(&self) -> String {
+        let mut csv = String::from("first,second,matches,first_wins,second_wins,draws,first_win_rate,average_ticks\n");
+        for pairing in &self.pairings {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{:.4},{:.1}",
+                pairing.first.name(),
+                pairing.second.name(),
+                pairing.matches,
+                pairing.first_wins,
+                pairing.second_wins,
+                pairing.draws,
+                ratio(pairing.first_wins as i64, pairing.matches),
+                ratio(pairing.total_ticks, pairing.matches)
+            );
+        }
+        csv
+    }
+
+    pub fn maps_csv(&self) -> String {
+        let mut csv = String::from("seed,matches,first_seat_wins,second_seat_wins,draws,average_ticks\n");
+        for map in &self.maps {
+            let seat_wins = |seat: usize| map.seat_wins.get(seat).copied().unwrap_or(0);
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{:.1}",
+                map.seed,
+                map.matches,
+                seat_wins(0),
+                seat_wins(1),
+                map.draws,
+                ratio(map.total_ticks, map.matches)
+            );
+        }
+        csv
+    }
+
+    /// Writes summary.json with everything plus one csv per table into `dir`.
+    pub fn write_to_dir(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(dir.join("summary.json"), json)?;
+        std::fs::write(dir.join("ais.csv"), self.ais_csv())?;
+        std::fs::write(dir.join("pairings.csv"), self.pairings_csv())?;
+        std::fs::write(dir.join("maps.csv"), self.maps_csv())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(seed: u64, seats: [AiKind; 2], winning_seat: Option<usize>, ticks: i32) -> MatchRecord {
+        MatchRecord {
+            seed,
+            seats: seats.to_vec(),
+            winners: winning_seat.map(|seat| vec![seats[seat]]).unwrap_or_default(),
+            winning_seats: winning_seat.into_iter().collect(),
+            reason: MatchEndReason::Elimination,
+            ticks,
+        }
+    }
+
+    #[test]
+    fn report_adds_up_matches() {
+        let greedy = AiKind::GreedyNearest;
+        let rush = AiKind::Rush;
+        let report = TournamentReport::new(
+            MatchRules::default(),
+            vec![
+                record(1, [greedy, rush], Some(0), 100),
+                record(1, [rush, greedy], Some(0), 200),
+                record(2, [greedy, rush], None, 300),
+            ],
+        );
+
+        assert_eq!(report.match_count, 3);
+        assert_eq!(report.average_ticks, 200.0);
+
+        let greedy_stats = report.ais.iter().find(|ai| ai.ai == greedy).unwrap();
+        assert_eq!((greedy_stats.wins, greedy_stats.losses, greedy_stats.draws), (1, 1, 1));
+        let defensive_stats = report.ais.iter().find(|ai| ai.ai == AiKind::Defensive).unwrap();
+        assert_eq!(defensive_stats.matches, 0);
+
+        assert_eq!(report.pairings.len(), 2);
+        assert_eq!(report.pairings[0].first_wins, 1);
+        assert_eq!(report.pairings[0].draws, 1);
+
+        assert_eq!(report.maps.len(), 2);
+        assert_eq!(report.maps[0].seat_wins, vec![2, 0]);
+        assert_eq!(report.maps[1].draws, 1);
+    }
+
+    #[test]
+    fn csv_has_a_row_per_entry() {
+        let report = TournamentReport::new(
+            MatchRules::default(),
+            vec![record(7, [AiKind::Defensive, AiKind::Rush], Some(1), 50)],
+        );
+
+        assert_eq!(report.ais_csv().lines().count(), 1 + AiKind::ALL.len());
+        assert_eq!(
+            report.pairings_csv().lines().nth(1),
+            Some("Defensive,Rush,1,0,1,0,0.0000,50.0")
+        );
+        assert_eq!(report.maps_csv().lines().nth(1), Some("7,1,0,1,0,50.0"));
+    }
+}